@@ -1,9 +1,13 @@
 use std::fmt::{ self, Display, Formatter };
 use crate::env::Env;
+use std::collections::HashMap;
+use std::path::{ Path, PathBuf };
+use hir::Item;
 
 pub mod hir;
 pub mod mir;
 mod parser;
+mod stdlib;
 
 fn write_iter<I: IntoIterator>(f: &mut Formatter, iter: I, sep: &str) -> fmt::Result where I::Item: Display {
     let mut iter = iter.into_iter();
@@ -29,15 +33,218 @@ fn write_iter_with<I: IntoIterator, F: FnMut(I::Item, &mut Formatter) -> fmt::Re
     Ok(())
 }
 
-pub fn parse(s: &str) -> Result<(Vec<mir::Func>, Env<mir::FuncSign>), String> {
-    let iter = parser::parse(s)?;
-    let mut env = Env::default();
-    let mut funcs = vec![];
-    for (name, func) in iter {
-        let (sign, func) = func.lower(&env, funcs.len());
-        println!("{}{} {}", name, sign, func);
-        env.insert(name, sign);
-        funcs.push(func);
+/// Adds the functions imported from `file` to the scope of the file at `path`, or only `func` if given.
+/// Functions the importing file defines itself are left out, as they shadow imported ones.
+fn add_imports(scope: &mut Env<mir::FuncSign>, defined: &Env<mir::FuncSign>, path: &Path, file: &Path, imported: Env<mir::FuncSign>, func: Option<String>) -> Result<(), String> {
+    let imported: Vec<_> = match func {
+        None => imported.into_iter().collect(),
+        Some(name) => {
+            let sign = imported.get(&name).ok_or_else(||format!("{} does not define {}", file.display(), name))?.clone();
+            vec![(name, sign)]
+        },
+    };
+    for (name, sign) in imported {
+        match scope.get(&name) {
+            None => scope.insert(name, sign),
+            // The same function may be imported more than once.
+            Some(old) if old.id == sign.id || defined.get(&name).is_some() => {},
+            Some(_) => return Err(format!("{}: {} is imported from two files", path.display(), name)),
+        }
+    }
+    Ok(())
+}
+
+/// Lowers a source file and the files it imports into one list of functions.
+/// Each file has its own namespace: calls resolve to the file's own functions and those it imports,
+/// so files may define functions with the same name as long as no file sees both.
+struct Loader {
+    funcs: Vec<mir::Func>,
+    signs: Vec<mir::FuncSign>,
+    /// The functions defined by each loaded file, by canonical path.
+    files: HashMap<PathBuf, Env<mir::FuncSign>>,
+    /// The files being loaded, each importing the next.
+    stack: Vec<PathBuf>,
+    /// The path and contents of every file read.
+    contents: Vec<u8>,
+}
+
+impl Loader {
+    /// Lowers a file, returning the functions it defines and every function it can call, imports included.
+    /// A function defined in the file shadows an imported one of the same name.
+    /// Only the functions of the `top` file, the one being simulated, are printed as they are lowered.
+    fn file(&mut self, s: &str, path: &Path, top: bool) -> Result<(Env<mir::FuncSign>, Env<mir::FuncSign>), String> {
+        let dir = path.parent().unwrap_or_else(||Path::new(""));
+        let mut scope: Env<mir::FuncSign> = Env::default();
+        let mut defined: Env<mir::FuncSign> = Env::default();
+        for item in parser::parse(s).map_err(|e|format!("{}: {}", path.display(), e))? {
+            match item {
+                Item::Import(file, func) => {
+                    let file = dir.join(file);
+                    let imported = self.import(&file, None)?;
+                    add_imports(&mut scope, &defined, path, &file, imported, func)?;
+                },
+                Item::ImportStd(module, func) => {
+                    let src = stdlib::module(&module).ok_or_else(||format!("{}: std has no module {}", path.display(), module))?;
+                    let name = PathBuf::from(format!("std::{}", module));
+                    let imported = self.import(&name, Some(src))?;
+                    add_imports(&mut scope, &defined, path, &name, imported, Some(func))?;
+                },
+                Item::Func(name, mut func) => {
+                    if hir::BUILTINS.contains(&name.as_str()) {
+                        return Err(format!("{}: {} is a built-in component, so it can't be redefined", path.display(), name));
+                    }
+                    if defined.get(&name).is_some() {
+                        return Err(format!("{}: {} is already defined", path.display(), name));
+                    }
+                    func.load_files(dir, &mut self.contents)?;
+                    let (sign, func) = func.lower(&name, &scope, self.funcs.len());
+                    if top {
+                        println!("{}{} {}", name, sign, func);
+                    }
+                    scope.replace(name.clone(), sign.clone());
+                    defined.insert(name, sign.clone());
+                    self.funcs.push(func);
+                    self.signs.push(sign);
+                },
+            }
+        }
+        Ok((defined, scope))
+    }
+    /// Loads an imported file once, however many files import it.
+    /// Standard library modules are `embedded`, so they are known by their name, as in `std::adders`, rather than a canonical path.
+    fn import(&mut self, path: &Path, embedded: Option<&str>) -> Result<Env<mir::FuncSign>, String> {
+        let canonical = match embedded {
+            Some(_) => path.to_owned(),
+            None => path.canonicalize().map_err(|e|format!("{}: {}", path.display(), e))?,
+        };
+        if let Some(start) = self.stack.iter().position(|p|*p == canonical) {
+            let cycle: Vec<_> = self.stack[start..].iter().chain(Some(&canonical)).map(|p|p.display().to_string()).collect();
+            return Err(format!("Import cycle: {}", cycle.join(" -> ")));
+        }
+        if let Some(defined) = self.files.get(&canonical) {
+            return Ok(defined.clone());
+        }
+        let src = match embedded {
+            Some(src) => src.to_owned(),
+            None => std::fs::read_to_string(path).map_err(|e|format!("{}: {}", path.display(), e))?,
+        };
+        self.contents.extend(format!("{}\0{}\0", canonical.display(), src).bytes());
+        self.stack.push(canonical.clone());
+        let (defined, _) = self.file(&src, path, false)?;
+        self.stack.pop();
+        self.files.insert(canonical, defined.clone());
+        Ok(defined)
+    }
+}
+
+/// A source lowered together with the files it imports.
+pub struct Program {
+    /// Every lowered function, by id.
+    pub funcs: Vec<mir::Func>,
+    /// The signature of each function, by id.
+    pub signs: Vec<mir::FuncSign>,
+    /// The functions the source can call by name, those it defines and those it imports.
+    pub env: Env<mir::FuncSign>,
+    /// The path and contents of every other file read, imported standard library modules included,
+    /// which a cached circuit depends on along with the source.
+    pub contents: Vec<u8>,
+}
+
+/// Parses and lowers the source read from `path`, along with the files it imports.
+/// Other files it refers to, such as ROM contents, are relative to the file that names them.
+pub fn parse(s: &str, path: &Path) -> Result<Program, String> {
+    let mut loader = Loader { funcs: vec![], signs: vec![], files: HashMap::new(), stack: vec![], contents: vec![] };
+    loader.stack.extend(path.canonicalize().ok());
+    let (_, env) = loader.file(s, path, true)?;
+    Ok(Program { funcs: loader.funcs, signs: loader.signs, env, contents: loader.contents })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use std::fs::{ create_dir_all, remove_dir_all, write };
+    use std::path::PathBuf;
+
+    /// A directory of source files in the temporary directory, removed when dropped.
+    struct Dir(PathBuf);
+
+    impl Dir {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let dir = std::env::temp_dir().join(format!("circuit-sim-{}-{}", std::process::id(), name));
+            create_dir_all(&dir).unwrap();
+            for (file, src) in files {
+                write(dir.join(file), src).unwrap();
+            }
+            Dir(dir.canonicalize().unwrap())
+        }
+        fn parse(&self, src: &str) -> Result<super::Program, String> {
+            parse(src, &self.0.join("top.cir"))
+        }
+        /// The outputs of `func` in `src` for each value of its one input.
+        fn outputs(&self, src: &str, func: &str) -> [Vec<bool>; 2] {
+            let program = self.parse(src).unwrap();
+            let sign = &program.env[func];
+            let mut circuit = program.funcs[sign.id].build_circuit(&program.funcs, sign);
+            let mut state = circuit.new_state();
+            [false, true].map(|x|{
+                circuit.set_input(vec![x]).unwrap();
+                for _ in 0..16 {
+                    circuit.update(&mut state);
+                }
+                circuit.get_output(&state)
+            })
+        }
+    }
+
+    impl Drop for Dir {
+        fn drop(&mut self) {
+            let _ = remove_dir_all(&self.0);
+        }
+    }
+
+    const A: &str = "g(x) -> o { o = not(x); } h(x) -> o { o = g(x); }";
+    const B: &str = "g(x) -> o { o = buffer(x); }";
+
+    #[test]
+    fn namespaces() {
+        let dir = Dir::new("namespaces", &[("a.cir", A), ("b.cir", B), ("c.cir", "import a::h; k(x) -> o { o = h(x); }")]);
+        let [low, high] = dir.outputs("import a::h; import b::g; top(x) -> (p, q) { p = h(x); q = g(x); }", "top");
+        assert_eq!((low, high), (vec![true, false], vec![false, true]));
+        let [low, high] = dir.outputs("import \"b.cir\"; g(x) -> o { o = not(x); } top(x) -> o { o = g(x); }", "top");
+        assert_eq!((low, high), (vec![true], vec![false]));
+        let program = dir.parse("import a::h; import c::k; import a::h;").unwrap();
+        assert_eq!(program.funcs.len(), 3);
+        assert_eq!(program.env["h"].id, 1);
+        assert!(program.env.get("g").is_none());
+    }
+
+    #[test]
+    fn std_modules() {
+        let dir = Dir::new("std_modules", &[]);
+        let [low, high] = dir.outputs("import std::gates::xor3; majority(x) -> o { o = xor3(x, x, x); }", "majority");
+        assert_eq!((low, high), (vec![false], vec![true]));
+        let err = dir.parse("import std::alu::add;").err().unwrap();
+        assert_eq!(err, format!("{}: std has no module alu", dir.0.join("top.cir").display()));
+    }
+
+    #[test]
+    fn errors() {
+        let dir = Dir::new("errors", &[("a.cir", A), ("b.cir", B)]);
+        let top = dir.0.join("top.cir").display().to_string();
+        assert_eq!(dir.parse("import \"a.cir\"; import \"b.cir\";").err().unwrap(), format!("{}: g is imported from two files", top));
+        assert_eq!(dir.parse("import b::h;").err().unwrap(), format!("{} does not define h", dir.0.join("b.cir").display()));
+        assert_eq!(dir.parse("f() {} f() {}").err().unwrap(), format!("{}: f is already defined", top));
+        assert_eq!(dir.parse("mux() {}").err().unwrap(), format!("{}: mux is a built-in component, so it can't be redefined", top));
+        assert!(dir.parse("import c::f;").err().unwrap().starts_with(&format!("{}: ", dir.0.join("c.cir").display())));
+    }
+
+    #[test]
+    fn import_cycles() {
+        let dir = Dir::new("import_cycles", &[("x.cir", "import \"y.cir\";"), ("y.cir", "import \"x.cir\";"), ("top.cir", "import \"top.cir\";")]);
+        let (x, y) = (dir.0.join("x.cir"), dir.0.join("y.cir"));
+        let err = parse("import \"y.cir\";", &x).err().unwrap();
+        assert_eq!(err, format!("Import cycle: {} -> {} -> {}", x.display(), y.display(), x.display()));
+        let top = dir.0.join("top.cir").display().to_string();
+        assert_eq!(dir.parse("import \"top.cir\";").err().unwrap(), format!("Import cycle: {} -> {}", top, top));
     }
-    Ok((funcs, env))
 }
@@ -1,18 +1,32 @@
 use super::{ write_iter, write_iter_with };
 use std::fmt::{ self, Display, Formatter };
 
+mod files;
 mod to_mir;
 
+pub use to_mir::BUILTINS;
+
 pub enum StateAst {
     Const(bool),
+    Num(usize),
+    Str(String),
     Ident(String),
     Not(Box<StateAst>),
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    And,
+    Or,
+    Xor,
+}
+
 pub enum Ast {
     Source(bool),
     Wire(String),
     Call(String, Option<Vec<StateAst>>, Vec<Ast>),
+    Not(Box<Ast>),
+    Binary(Op, Box<Ast>, Box<Ast>),
 }
 
 pub enum Stmt {
@@ -22,6 +36,15 @@ pub enum Stmt {
     Call(Ast),
 }
 
+/// A top level item of a source file.
+pub enum Item {
+    /// The file to import, relative to the importing file, and the one function to import from it, if not all.
+    Import(String, Option<String>),
+    /// A standard library module and the function to import from it, as in `import std::adders::full_adder;`.
+    ImportStd(String, String),
+    Func(String, Func),
+}
+
 pub struct Func {
     pub state: Vec<(String, bool)>,
     pub input: Vec<String>,
@@ -32,17 +55,66 @@ pub struct Func {
 impl Display for StateAst {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
-            StateAst::Const(b) => write!(f, "{}", b),
+            StateAst::Const(b) => write!(f, "{}", *b as u8),
+            StateAst::Num(n) => write!(f, "{}", n),
+            StateAst::Str(s) => write!(f, "{:?}", s),
             StateAst::Ident(i) => write!(f, "{}", i),
             StateAst::Not(ast) => write!(f, "!{}", ast),
         }
     }
 }
 
+impl Op {
+    /// Binding strength, where `&` binds tighter than `^`, which binds tighter than `|`.
+    fn precedence(self) -> u8 {
+        match self {
+            Op::Or => 1,
+            Op::Xor => 2,
+            Op::And => 3,
+        }
+    }
+}
+
+impl Display for Op {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Op::And => write!(f, "&"),
+            Op::Or => write!(f, "|"),
+            Op::Xor => write!(f, "^"),
+        }
+    }
+}
+
+impl Ast {
+    fn precedence(&self) -> u8 {
+        match self {
+            Ast::Binary(op, _, _) => op.precedence(),
+            _ => 4,
+        }
+    }
+    /// Writes the expression, in parentheses if it binds looser than `precedence`.
+    fn fmt_operand(&self, f: &mut Formatter, precedence: u8) -> fmt::Result {
+        if self.precedence() < precedence {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
 impl Display for Ast {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
-            Ast::Source(b) => write!(f, "{}", b),
+            Ast::Not(ast) => {
+                write!(f, "!")?;
+                ast.fmt_operand(f, 4)
+            },
+            Ast::Binary(op, a, b) => {
+                a.fmt_operand(f, op.precedence())?;
+                write!(f, " {} ", op)?;
+                b.fmt_operand(f, op.precedence() + 1)
+            },
+            Ast::Source(b) => write!(f, "{}", *b as u8),
             Ast::Wire(i) => write!(f, "{}", i),
             Ast::Call(func, state, param) => {
                 write!(f, "{}", func)?;
@@ -106,3 +178,59 @@ impl Display for Func {
         write!(f, "}}")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ Item, Stmt };
+    use crate::ast::{ parse, parser };
+    use std::path::Path;
+
+    /// Parses `expr` as the body of a function and prints it back.
+    fn print(expr: &str) -> String {
+        let src = format!("f(a, b, c, d) -> o {{ o = {}; }}", expr);
+        let item = parser::parse(&src).unwrap().next();
+        match item {
+            Some(Item::Func(_, func)) => match &func.stmts[..] {
+                [Stmt::Set(_, ast)] => ast.to_string(),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn precedence_printing() {
+        let cases = [
+            ("a | b & c", "a | b & c"),
+            ("(a | b) & c", "(a | b) & c"),
+            ("a & b ^ c | d", "a & b ^ c | d"),
+            ("a & (b ^ (c | d))", "a & (b ^ (c | d))"),
+            ("(a ^ b) ^ c", "a ^ b ^ c"),
+            ("a ^ (b ^ c)", "a ^ (b ^ c)"),
+            ("((a))", "a"),
+            ("!(a & b)", "!(a & b)"),
+            ("!!a | !b", "!!a | !b"),
+            ("and[0](a | b, !c) & 1", "and[0](a | b, !c) & 1"),
+        ];
+        for (src, printed) in cases {
+            assert_eq!(print(src), printed, "{}", src);
+            assert_eq!(print(printed), printed, "{}", printed);
+        }
+    }
+
+    #[test]
+    fn precedence_lowering() {
+        let program = parse("f(a, b, c, d) -> o { o = a | !b & c ^ d; }", Path::new("test.cir")).unwrap();
+        let sign = &program.env["f"];
+        let mut circuit = program.funcs[sign.id].build_circuit(&program.funcs, sign);
+        let mut state = circuit.new_state();
+        for value in 0..16 {
+            let [a, b, c, d] = [0, 1, 2, 3].map(|i|value >> i & 1 == 1);
+            circuit.set_input(vec![a, b, c, d]).unwrap();
+            for _ in 0..16 {
+                circuit.update(&mut state);
+            }
+            assert_eq!(circuit.get_output(&state), [a | (!b & c) ^ d], "{:04b}", value);
+        }
+    }
+}
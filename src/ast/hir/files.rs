@@ -0,0 +1,40 @@
+use super::{ StateAst, Ast, Stmt, Func };
+use circuit_sim::memory::read_words;
+use std::path::Path;
+
+impl Ast {
+    fn load_files(&mut self, dir: &Path, contents: &mut Vec<u8>) -> Result<(), String> {
+        match self {
+            Ast::Source(_) | Ast::Wire(_) => Ok(()),
+            Ast::Call(_, state, args) => {
+                if let Some(state) = state {
+                    if let [StateAst::Str(path)] = &state[..] {
+                        let path = dir.join(path);
+                        let words = read_words(&path)?;
+                        contents.extend(format!("{}\0", path.display()).bytes());
+                        contents.extend(words.iter().flat_map(|w|w.to_le_bytes()));
+                        *state = words.into_iter().map(|w|StateAst::Num(w as usize)).collect();
+                    }
+                }
+                args.iter_mut().try_for_each(|arg|arg.load_files(dir, contents))
+            },
+            Ast::Not(ast) => ast.load_files(dir, contents),
+            Ast::Binary(_, a, b) => {
+                a.load_files(dir, contents)?;
+                b.load_files(dir, contents)
+            },
+        }
+    }
+}
+
+impl Func {
+    /// Replaces a file name given as the only state of a call, as in `rom["table.hex"](a0, a1)`,
+    /// with the words in the file. Paths are relative to `dir`, the directory of the source.
+    /// The path and words of each file read are added to `contents`.
+    pub fn load_files(&mut self, dir: &Path, contents: &mut Vec<u8>) -> Result<(), String> {
+        self.stmts.iter_mut().try_for_each(|stmt|match stmt {
+            Stmt::Float(_) => Ok(()),
+            Stmt::Let(_, ast) | Stmt::Set(_, ast) | Stmt::Call(ast) => ast.load_files(dir, contents),
+        })
+    }
+}
@@ -1,7 +1,14 @@
-use super::{ StateAst, Ast, Stmt, Func };
+use super::{ StateAst, Op, Ast, Stmt, Func };
 use crate::ast::mir;
 use crate::env::Env;
 use std::convert::TryInto;
+use circuit_sim::base::{ BusMode, Memory };
+
+/// The components calls lower to directly, which functions can't be named after as calls would never reach them.
+pub const BUILTINS: &[&str] = &[
+    "and", "buffer", "bus", "bus_input", "clock", "decoder", "encoder", "mux", "nand", "nor", "not", "or", "ram", "rom",
+    "source", "sync_ram", "xnor", "xor",
+];
 
 impl StateAst {
     fn lower(self, states: &Env<usize>) -> mir::StateAst {
@@ -10,6 +17,8 @@ impl StateAst {
         let state = loop {
             ast = match ast {
                 StateAst::Const(b) => break mir::StateRef::Const(b),
+                StateAst::Num(n) => panic!("Expected a state, but recieved {}", n),
+                StateAst::Str(s) => panic!("Expected a state, but recieved {:?}", s),
                 StateAst::Ident(i) => break mir::StateRef::Ident(states[&i]),
                 StateAst::Not(inner) => {
                     negate = !negate;
@@ -34,12 +43,131 @@ fn single_state(func: &str, default: bool, state: Option<Vec<StateAst>>, states:
     }
 }
 
+/// Reads the state of a bus: an optional mode such as `pullup`, then an optional initial value,
+/// which for pulled buses defaults to the value they are pulled to.
+/// A state named after a mode would be read as the mode, so it can't be passed to a bus.
+fn bus_state(state: Option<Vec<StateAst>>, states: &Env<usize>) -> (mir::StateAst, BusMode) {
+    let mut state = state.unwrap_or_default();
+    let mode = match state.first() {
+        Some(StateAst::Ident(name)) if BusMode::parse(name).is_some() => {
+            if states.get(name).is_some() {
+                panic!("bus[{}] is ambiguous, as {} is both a bus mode and a state", name, name)
+            }
+            BusMode::parse(name)
+        },
+        _ => None,
+    };
+    if mode.is_some() {
+        state.remove(0);
+    }
+    let mode = mode.unwrap_or(BusMode::Float);
+    let init = if state.is_empty() { None } else { Some(state) };
+    (single_state("bus", mode.pull().unwrap_or(false), init, states), mode)
+}
+
+/// Reads the `[period, high, phase]` of a clock, where `high` defaults to half the period and `phase` to 0.
+fn clock_state(state: Option<Vec<StateAst>>) -> (usize, usize, usize) {
+    let state: Vec<_> = state.unwrap_or_default().into_iter().map(|s|match s {
+        StateAst::Num(n) => n,
+        StateAst::Const(b) => b as usize,
+        s => panic!("clock takes numbers, but recieved {}", s),
+    }).collect();
+    let (period, high, phase) = match state[..] {
+        [period] => (period, period / 2, 0),
+        [period, high] => (period, high, 0),
+        [period, high, phase] => (period, high, phase),
+        _ => panic!("clock takes 1 to 3 numbers, but recieved {}", state.len()),
+    };
+    if period == 0 || high > period {
+        panic!("clock[{}, {}] can't be high for longer than its period", period, high)
+    }
+    (period, high, phase)
+}
+
 fn expect_input(func: &str, expected: usize, input: usize) {
     if input != expected {
         panic!("{} takes {} input, but recieved {}", func, expected, input)
     }
 }
 
+fn expect_no_state(func: &str, state: Option<Vec<StateAst>>) {
+    if let Some(state) = state {
+        if !state.is_empty() {
+            panic!("{} takes 0 state, but recieved {}", func, state.len())
+        }
+    }
+}
+
+/// The number of select wires needed to pick one of `n` wires.
+fn select_width(n: usize) -> usize {
+    (usize::BITS - n.saturating_sub(1).leading_zeros()) as usize
+}
+
+/// Splits the inputs of a mux into its data and select wires.
+fn mux_inputs(mut input: Vec<usize>) -> (Vec<usize>, Vec<usize>) {
+    let count = input.len();
+    let data = (2..=count).find(|n|n + select_width(*n) == count)
+        .unwrap_or_else(||panic!("mux takes N data inputs and log2 N select inputs, but recieved {} inputs", count));
+    let select = input.split_off(data);
+    (input, select)
+}
+
+fn decoder_outputs(input: usize) -> usize {
+    if input == 0 || input >= usize::BITS as usize {
+        panic!("decoder takes 1 to {} inputs, but recieved {}", usize::BITS - 1, input)
+    }
+    1 << input
+}
+
+fn encoder_outputs(input: usize) -> usize {
+    if input < 2 {
+        panic!("encoder takes at least 2 inputs, but recieved {}", input)
+    }
+    select_width(input)
+}
+
+/// Reads the words of a ROM or the initial words of a RAM with `width` data bits, one per address.
+fn memory_words(func: &str, state: Option<Vec<StateAst>>, address: usize, width: usize) -> Vec<u64> {
+    let words: Vec<_> = state.unwrap_or_default().into_iter().map(|s|match s {
+        StateAst::Num(n) => n as u64,
+        StateAst::Const(b) => b as u64,
+        s => panic!("{} takes numbers or a file name, but recieved {}", func, s),
+    }).collect();
+    if width == 0 || width > 64 {
+        panic!("{} gives 1 to 64 outputs, but expected {}", func, width)
+    }
+    if address >= usize::BITS as usize || words.len() > 1 << address {
+        panic!("{} with {} address inputs holds at most {} words, but recieved {}", func, address, 1usize.checked_shl(address as u32).unwrap_or(0), words.len())
+    }
+    if let Some(word) = words.iter().find(|w|width < 64 && **w >> width != 0) {
+        panic!("{} word {:#x} does not fit in {} bits", func, word, width)
+    }
+    words
+}
+
+/// Lowers a RAM, whose inputs are its address, `outputs.len()` data inputs, the write enable and, if `clocked`, the clock.
+fn lower_ram(func: &str, state: Option<Vec<StateAst>>, mut input: Vec<usize>, outputs: Vec<usize>, clocked: bool) -> mir::Stmt {
+    let ports = outputs.len() + 1 + clocked as usize;
+    if input.len() <= ports || input.len() - ports > Memory::MAX_ADDRESS {
+        panic!("{} takes 1 to {} address inputs, {} data inputs and {} control inputs, but recieved {} inputs",
+            func, Memory::MAX_ADDRESS, outputs.len(), ports - outputs.len(), input.len())
+    }
+    let clock = if clocked { input.pop() } else { None };
+    let write = input.pop().unwrap();
+    let data = input.split_off(input.len() - outputs.len());
+    let words = memory_words(func, state, input.len(), outputs.len());
+    mir::Stmt::Ram { words, address: input, data, write, clock, outputs }
+}
+
+fn expect_gate(func: &str, input: usize, output: usize) {
+    if input == 0 {
+        panic!("{} takes at least 1 input, but recieved 0", func)
+    }
+    if output != 1 {
+        panic!("{} gives 1 output, but expected {}", func, output)
+    }
+}
+
 fn expect_io(func: &str, exp_in: usize, exp_out: usize, input: usize, output: usize) {
     if input != exp_in {
         panic!("{} takes {} input, but recieved {}", func, exp_in, input)
@@ -49,7 +177,34 @@ fn expect_io(func: &str, exp_in: usize, exp_out: usize, input: usize, output: us
     }
 }
 
+impl Op {
+    fn lower(self, stmts: &mut Vec<mir::Stmt>, inputs: Vec<usize>, output: usize) {
+        stmts.push(match self {
+            Op::And => mir::Stmt::And(false.into(), inputs, output),
+            Op::Or => mir::Stmt::Or(false.into(), inputs, output),
+            Op::Xor => mir::Stmt::Xor(false.into(), inputs[0], inputs[1], output),
+        })
+    }
+}
+
 impl Ast {
+    fn operand(self, stmts: &mut Vec<mir::Stmt>, wire_count: &mut usize, funcs: &Env<mir::FuncSign>, states: &Env<usize>, wires: &Env<usize>) -> usize {
+        let wires = self.lower(stmts, wire_count, funcs, states, wires);
+        if wires.len() != 1 {
+            panic!("Operators take single wires, but recieved {}", wires.len())
+        }
+        wires[0]
+    }
+    /// Collects the operands of a chain of `&` or `|`, so the chain becomes a single wide gate.
+    fn flatten(self, op: Op, operands: &mut Vec<Ast>) {
+        match self {
+            Ast::Binary(inner, a, b) if inner == op && op != Op::Xor => {
+                a.flatten(op, operands);
+                b.flatten(op, operands);
+            },
+            ast => operands.push(ast),
+        }
+    }
     fn lower_to(self, stmts: &mut Vec<mir::Stmt>, wire_count: &mut usize, output: Vec<usize>, funcs: &Env<mir::FuncSign>, states: &Env<usize>, wires: &Env<usize>) {
         match self {
             Ast::Source(b) => {
@@ -57,6 +212,19 @@ impl Ast {
                 stmts.push(mir::Stmt::Source(b.into(), output));
             },
             Ast::Wire(_) => panic!("Can't connect 2 wires"),
+            Ast::Not(ast) => {
+                let input = ast.operand(stmts, wire_count, funcs, states, wires);
+                expect_io("!", 1, 1, 1, output.len());
+                stmts.push(mir::Stmt::Inverter(true.into(), input, output[0]))
+            },
+            Ast::Binary(op, a, b) => {
+                let mut operands = vec![];
+                a.flatten(op, &mut operands);
+                b.flatten(op, &mut operands);
+                let inputs: Vec<_> = operands.into_iter().map(|ast|ast.operand(stmts, wire_count, funcs, states, wires)).collect();
+                expect_io(&op.to_string(), inputs.len(), 1, inputs.len(), output.len());
+                op.lower(stmts, inputs, output[0])
+            },
             Ast::Call(func, state, param) => {
                 let input: Vec<_> = param.into_iter().flat_map(|ast|ast.lower(stmts, wire_count, funcs, states, wires)).collect();
                 match func.as_str() {
@@ -72,35 +240,68 @@ impl Ast {
                     },
                     "or" => {
                         let state = single_state("or", false, state, states);
-                        expect_io("or", 2, 1, input.len(), output.len());
-                        stmts.push(mir::Stmt::Or(state, input[0], input[1], output[0]))
+                        expect_gate("or", input.len(), output.len());
+                        stmts.push(mir::Stmt::Or(state, input, output[0]))
                     },
                     "and" => {
                         let state = single_state("and", false, state, states);
-                        expect_io("and", 2, 1, input.len(), output.len());
-                        stmts.push(mir::Stmt::And(state, input[0], input[1], output[0]))
+                        expect_gate("and", input.len(), output.len());
+                        stmts.push(mir::Stmt::And(state, input, output[0]))
                     },
                     "nor" => {
                         let state = single_state("nor", true, state, states);
-                        expect_io("nor", 2, 1, input.len(), output.len());
-                        stmts.push(mir::Stmt::Nor(state, input[0], input[1], output[0]))
+                        expect_gate("nor", input.len(), output.len());
+                        stmts.push(mir::Stmt::Nor(state, input, output[0]))
                     },
                     "nand" => {
                         let state = single_state("nand", true, state, states);
-                        expect_io("nand", 2, 1, input.len(), output.len());
-                        stmts.push(mir::Stmt::Nand(state, input[0], input[1], output[0]))
+                        expect_gate("nand", input.len(), output.len());
+                        stmts.push(mir::Stmt::Nand(state, input, output[0]))
+                    },
+                    "xor" => {
+                        let state = single_state("xor", false, state, states);
+                        expect_io("xor", 2, 1, input.len(), output.len());
+                        stmts.push(mir::Stmt::Xor(state, input[0], input[1], output[0]))
+                    },
+                    "xnor" => {
+                        let state = single_state("xnor", true, state, states);
+                        expect_io("xnor", 2, 1, input.len(), output.len());
+                        stmts.push(mir::Stmt::Xnor(state, input[0], input[1], output[0]))
                     },
                     "bus" => {
-                        let state = single_state("bus", false, state, states);
+                        let (state, mode) = bus_state(state, states);
                         expect_io("bus", 0, 1, input.len(), output.len());
-                        stmts.push(mir::Stmt::Bus(state, output[0]))
+                        stmts.push(mir::Stmt::Bus(state, mode, output[0]))
+                    },
+                    "clock" => {
+                        let (period, high, phase) = clock_state(state);
+                        expect_io("clock", 0, 1, input.len(), output.len());
+                        stmts.push(mir::Stmt::Clock { period, high, phase, output: output[0] })
+                    },
+                    "mux" => {
+                        expect_no_state("mux", state);
+                        expect_io("mux", input.len(), 1, input.len(), output.len());
+                        let (data, select) = mux_inputs(input);
+                        stmts.push(mir::Stmt::Mux(data, select, output[0]))
+                    },
+                    "decoder" => {
+                        expect_no_state("decoder", state);
+                        expect_io("decoder", input.len(), decoder_outputs(input.len()), input.len(), output.len());
+                        stmts.push(mir::Stmt::Decoder(input, output))
                     },
+                    "encoder" => {
+                        expect_no_state("encoder", state);
+                        expect_io("encoder", input.len(), encoder_outputs(input.len()), input.len(), output.len());
+                        stmts.push(mir::Stmt::Encoder(input, output))
+                    },
+                    "rom" => {
+                        let words = memory_words("rom", state, input.len(), output.len());
+                        stmts.push(mir::Stmt::Rom(words, input, output))
+                    },
+                    "ram" => stmts.push(lower_ram("ram", state, input, output, false)),
+                    "sync_ram" => stmts.push(lower_ram("sync_ram", state, input, output, true)),
                     "bus_input" => {
-                        if let Some(state) = state {
-                            if !state.is_empty() {
-                                panic!("bus_input takes 0 state, but recieved {}", state.len())
-                            }
-                        }
+                        expect_no_state("bus_input", state);
                         expect_io("bus_input", 3, 0, input.len(), output.len());
                         stmts.push(mir::Stmt::BusInput(input[0], input[1], input[2]))
                     },
@@ -128,6 +329,23 @@ impl Ast {
                 vec![output]
             },
             Ast::Wire(i) => vec![wires[&i]],
+            Ast::Not(ast) => {
+                let input = ast.operand(stmts, wire_count, funcs, states, wires);
+                let output = *wire_count;
+                *wire_count += 1;
+                stmts.push(mir::Stmt::Inverter(true.into(), input, output));
+                vec![output]
+            },
+            Ast::Binary(op, a, b) => {
+                let mut operands = vec![];
+                a.flatten(op, &mut operands);
+                b.flatten(op, &mut operands);
+                let inputs: Vec<_> = operands.into_iter().map(|ast|ast.operand(stmts, wire_count, funcs, states, wires)).collect();
+                let output = *wire_count;
+                *wire_count += 1;
+                op.lower(stmts, inputs, output);
+                vec![output]
+            },
             Ast::Call(func, state, param) => {
                 let input: Vec<_> = param.into_iter().flat_map(|ast|ast.lower(stmts, wire_count, funcs, states, wires)).collect();
                 match func.as_str() {
@@ -157,50 +375,96 @@ impl Ast {
                     },
                     "or" => {
                         let state = single_state("or", false, state, states);
-                        expect_input("or", 2, input.len());
+                        expect_gate("or", input.len(), 1);
                         let output = *wire_count;
                         *wire_count += 1;
-                        stmts.push(mir::Stmt::Or(state, input[0], input[1], output));
+                        stmts.push(mir::Stmt::Or(state, input, output));
                         vec![output]
                     },
                     "and" => {
                         let state = single_state("and", false, state, states);
-                        expect_input("and", 2, input.len());
+                        expect_gate("and", input.len(), 1);
                         let output = *wire_count;
                         *wire_count += 1;
-                        stmts.push(mir::Stmt::And(state, input[0], input[1], output));
+                        stmts.push(mir::Stmt::And(state, input, output));
                         vec![output]
                     },
                     "nor" => {
                         let state = single_state("nor", true, state, states);
-                        expect_input("nor", 2, input.len());
+                        expect_gate("nor", input.len(), 1);
                         let output = *wire_count;
                         *wire_count += 1;
-                        stmts.push(mir::Stmt::Nor(state, input[0], input[1], output));
+                        stmts.push(mir::Stmt::Nor(state, input, output));
                         vec![output]
                     },
                     "nand" => {
                         let state = single_state("nand", true, state, states);
-                        expect_input("nand", 2, input.len());
+                        expect_gate("nand", input.len(), 1);
                         let output = *wire_count;
                         *wire_count += 1;
-                        stmts.push(mir::Stmt::Nand(state, input[0], input[1], output));
+                        stmts.push(mir::Stmt::Nand(state, input, output));
+                        vec![output]
+                    },
+                    "xor" => {
+                        let state = single_state("xor", false, state, states);
+                        expect_input("xor", 2, input.len());
+                        let output = *wire_count;
+                        *wire_count += 1;
+                        stmts.push(mir::Stmt::Xor(state, input[0], input[1], output));
+                        vec![output]
+                    },
+                    "xnor" => {
+                        let state = single_state("xnor", true, state, states);
+                        expect_input("xnor", 2, input.len());
+                        let output = *wire_count;
+                        *wire_count += 1;
+                        stmts.push(mir::Stmt::Xnor(state, input[0], input[1], output));
                         vec![output]
                     },
                     "bus" => {
-                        let state = single_state("bus", false, state, states);
+                        let (state, mode) = bus_state(state, states);
                         expect_input("bus", 0, input.len());
                         let output = *wire_count;
                         *wire_count += 1;
-                        stmts.push(mir::Stmt::Bus(state, output));
+                        stmts.push(mir::Stmt::Bus(state, mode, output));
+                        vec![output]
+                    },
+                    "clock" => {
+                        let (period, high, phase) = clock_state(state);
+                        expect_input("clock", 0, input.len());
+                        let output = *wire_count;
+                        *wire_count += 1;
+                        stmts.push(mir::Stmt::Clock { period, high, phase, output });
+                        vec![output]
+                    },
+                    "mux" => {
+                        expect_no_state("mux", state);
+                        let (data, select) = mux_inputs(input);
+                        let output = *wire_count;
+                        *wire_count += 1;
+                        stmts.push(mir::Stmt::Mux(data, select, output));
                         vec![output]
                     },
+                    "decoder" => {
+                        expect_no_state("decoder", state);
+                        let count = decoder_outputs(input.len());
+                        let output: Vec<_> = (*wire_count..*wire_count+count).collect();
+                        *wire_count += count;
+                        stmts.push(mir::Stmt::Decoder(input, output.clone()));
+                        output
+                    },
+                    "encoder" => {
+                        expect_no_state("encoder", state);
+                        let count = encoder_outputs(input.len());
+                        let output: Vec<_> = (*wire_count..*wire_count+count).collect();
+                        *wire_count += count;
+                        stmts.push(mir::Stmt::Encoder(input, output.clone()));
+                        output
+                    },
+                    "rom" => panic!("rom takes its data width from its outputs, so it must be assigned, as in (d0, d1) = rom[...](a0, a1)"),
+                    name @ ("ram" | "sync_ram") => panic!("{} takes its data width from its outputs, so it must be assigned, as in (q0, q1) = {}(a0, d0, d1, we)", name, name),
                     "bus_input" => {
-                        if let Some(state) = state {
-                            if !state.is_empty() {
-                                panic!("bus_input takes 0 state, but recieved {}", state.len())
-                            }
-                        }
+                        expect_no_state("bus_input", state);
                         expect_input("bus_input", 0, input.len());
                         stmts.push(mir::Stmt::BusInput(input[0], input[1], input[2]));
                         vec![]
@@ -255,7 +519,8 @@ impl Stmt {
 }
 
 impl Func {
-    pub fn lower(self, funcs: &Env<mir::FuncSign>, id: usize) -> (mir::FuncSign, mir::Func) {
+    pub fn lower(self, name: &str, funcs: &Env<mir::FuncSign>, id: usize) -> (mir::FuncSign, mir::Func) {
+        let state_names = self.state.iter().map(|(s, _)|s.clone()).collect();
         let (sign_state, states) = self.state.into_iter().enumerate().map(|(i, (s, v))|(v, (s, i))).unzip();
         let input_count = self.input.len();
         let output_count = self.output.len();
@@ -263,6 +528,8 @@ impl Func {
         let mut wires = self.input.into_iter().chain(self.output).enumerate().map(|(i, s)|(s, i)).collect();
         let mut stmts = vec![];
         self.stmts.into_iter().for_each(|stmt|stmt.lower(&mut stmts, &mut wire_count, funcs, &states, &mut wires));
+        let mut names: Vec<_> = wires.into_iter().collect();
+        names.sort_unstable_by(|(a, w_a), (b, w_b)|(w_a, a).cmp(&(w_b, b)));
         (
             mir::FuncSign {
                 id,
@@ -271,6 +538,9 @@ impl Func {
                 output: output_count,
             },
             mir::Func {
+                name: name.to_owned(),
+                states: state_names,
+                names,
                 local: wire_count - input_count - output_count,
                 stmts,
             }
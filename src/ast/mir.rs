@@ -1,8 +1,16 @@
-use super::{ write_iter };
+use super::{ write_iter, write_iter_with };
 use circuit_sim::circuit::{ Circuit, Builder };
-use circuit_sim::base::Component;
+use circuit_sim::base::{ BusMode, Component, Memory };
 use std::fmt::{ self, Display, Formatter };
 
+mod diagram;
+mod dump;
+mod verilog;
+
+pub use diagram::Hierarchy;
+pub use dump::{ is_dump, read_dump, write_dump };
+pub use verilog::write_verilog;
+
 pub enum StateRef {
     Const(bool),
     Ident(usize),
@@ -31,14 +39,37 @@ pub enum Stmt {
     Source(StateAst, usize),
     Buffer(StateAst, usize, usize),
     Inverter(StateAst, usize, usize),
-    Or(StateAst, usize, usize, usize),
-    And(StateAst, usize, usize, usize),
-    Nor(StateAst, usize, usize, usize),
-    Nand(StateAst, usize, usize, usize),
-    Bus(StateAst, usize),
+    Or(StateAst, Vec<usize>, usize),
+    And(StateAst, Vec<usize>, usize),
+    Nor(StateAst, Vec<usize>, usize),
+    Nand(StateAst, Vec<usize>, usize),
+    Xor(StateAst, usize, usize, usize),
+    Xnor(StateAst, usize, usize, usize),
+    Bus(StateAst, BusMode, usize),
+    Clock {
+        period: usize,
+        high: usize,
+        phase: usize,
+        output: usize,
+    },
+    Mux(Vec<usize>, Vec<usize>, usize),
+    Decoder(Vec<usize>, Vec<usize>),
+    Encoder(Vec<usize>, Vec<usize>),
+    /// Words, address inputs and data outputs, least significant first.
+    Rom(Vec<u64>, Vec<usize>, Vec<usize>),
+    /// A RAM, writing on the rising edge of `clock`, or whenever `write` is high without one.
+    Ram {
+        words: Vec<u64>,
+        address: Vec<usize>,
+        data: Vec<usize>,
+        write: usize,
+        clock: Option<usize>,
+        outputs: Vec<usize>,
+    },
     BusInput(usize, usize, usize),
 }
 
+#[derive(Clone)]
 pub struct FuncSign {
     pub id: usize,
     pub state: Vec<bool>,
@@ -47,6 +78,9 @@ pub struct FuncSign {
 }
 
 pub struct Func {
+    pub name: String,
+    pub states: Vec<String>,
+    pub names: Vec<(String, usize)>,
     pub local: usize,
     pub stmts: Vec<Stmt>,
 }
@@ -63,6 +97,12 @@ impl Display for StateAst {
     }
 }
 
+fn write_gate(f: &mut Formatter, name: &str, state: &StateAst, inputs: &[usize], output: usize) -> fmt::Result {
+    write!(f, "{} = {}[{}](", output, name, state)?;
+    write_iter(f, inputs, ", ")?;
+    write!(f, ");")
+}
+
 impl Display for Stmt {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
@@ -76,11 +116,46 @@ impl Display for Stmt {
             Stmt::Source(state, input) => write!(f, "source[{}]({});", state, input),
             Stmt::Buffer(state, input, output) => write!(f, "{} = buffer[{}]({});", output, state, input),
             Stmt::Inverter(state, input, output) => write!(f, "{} = not[{}]({});", output, state, input),
-            Stmt::Or(state, a, b, o) => write!(f, "{} = or[{}]({}, {});", o, state, a, b),
-            Stmt::And(state, a, b, o) => write!(f, "{} = and[{}]({}, {});", o, state, a, b),
-            Stmt::Nor(state, a, b, o) => write!(f, "{} = nor[{}]({}, {});", o, state, a, b),
-            Stmt::Nand(state, a, b, o) => write!(f, "{} = nand[{}]({}, {});", o, state, a, b),
-            Stmt::Bus(state, output) => write!(f, "{} = bus[{}]();", output, state),
+            Stmt::Or(state, inputs, o) => write_gate(f, "or", state, inputs, *o),
+            Stmt::And(state, inputs, o) => write_gate(f, "and", state, inputs, *o),
+            Stmt::Nor(state, inputs, o) => write_gate(f, "nor", state, inputs, *o),
+            Stmt::Nand(state, inputs, o) => write_gate(f, "nand", state, inputs, *o),
+            Stmt::Xor(state, a, b, o) => write!(f, "{} = xor[{}]({}, {});", o, state, a, b),
+            Stmt::Xnor(state, a, b, o) => write!(f, "{} = xnor[{}]({}, {});", o, state, a, b),
+            Stmt::Bus(state, BusMode::Float, output) => write!(f, "{} = bus[{}]();", output, state),
+            Stmt::Bus(state, mode, output) => write!(f, "{} = bus[{}, {}]();", output, mode.name(), state),
+            Stmt::Clock { period, high, phase, output } => write!(f, "{} = clock[{}, {}, {}]();", output, period, high, phase),
+            Stmt::Mux(data, select, o) => {
+                write!(f, "{} = mux(", o)?;
+                write_iter(f, data.iter().chain(select), ", ")?;
+                write!(f, ");")
+            },
+            Stmt::Decoder(input, output) | Stmt::Encoder(input, output) => {
+                let name = if let Stmt::Decoder(..) = self { "decoder" } else { "encoder" };
+                write!(f, "(")?;
+                write_iter(f, output, ", ")?;
+                write!(f, ") = {}(", name)?;
+                write_iter(f, input, ", ")?;
+                write!(f, ");")
+            },
+            Stmt::Rom(words, address, output) => {
+                write!(f, "(")?;
+                write_iter(f, output, ", ")?;
+                write!(f, ") = rom[")?;
+                write_iter_with(f, words, |w, f|write!(f, "{:#x}", w), ", ")?;
+                write!(f, "](")?;
+                write_iter(f, address, ", ")?;
+                write!(f, ");")
+            },
+            Stmt::Ram { words, address, data, write, clock, outputs } => {
+                write!(f, "(")?;
+                write_iter(f, outputs, ", ")?;
+                write!(f, ") = {}[", if clock.is_some() { "sync_ram" } else { "ram" })?;
+                write_iter_with(f, words, |w, f|write!(f, "{:#x}", w), ", ")?;
+                write!(f, "](")?;
+                write_iter(f, address.iter().chain(data).chain(Some(write)).chain(clock), ", ")?;
+                write!(f, ");")
+            },
             Stmt::BusInput(bus, high, low) => write!(f, "bus_input({}, {}, {})", bus, high, low),
         }
     }
@@ -132,20 +207,63 @@ impl Stmt {
             Stmt::Inverter(state, input, output) => {
                 circuit.place_component(p_wires[*output], Component::Inverter(p_wires[*input]), state.eval(states));
             },
-            Stmt::Or(state, a, b, o) => {
-                circuit.place_component(p_wires[*o], Component::Or(p_wires[*a], p_wires[*b]), state.eval(states));
+            Stmt::Or(state, inputs, o) => {
+                circuit.place_component(p_wires[*o], Component::Or(inputs.iter().map(|i|p_wires[*i]).collect()), state.eval(states));
             },
-            Stmt::And(state, a, b, o) => {
-                circuit.place_component(p_wires[*o], Component::And(p_wires[*a], p_wires[*b]), state.eval(states));
+            Stmt::And(state, inputs, o) => {
+                circuit.place_component(p_wires[*o], Component::And(inputs.iter().map(|i|p_wires[*i]).collect()), state.eval(states));
             },
-            Stmt::Nor(state, a, b, o) => {
-                circuit.place_component(p_wires[*o], Component::Nor(p_wires[*a], p_wires[*b]), state.eval(states));
+            Stmt::Nor(state, inputs, o) => {
+                circuit.place_component(p_wires[*o], Component::Nor(inputs.iter().map(|i|p_wires[*i]).collect()), state.eval(states));
             },
-            Stmt::Nand(state, a, b, o) => {
-                circuit.place_component(p_wires[*o], Component::Nand(p_wires[*a], p_wires[*b]), state.eval(states));
+            Stmt::Nand(state, inputs, o) => {
+                circuit.place_component(p_wires[*o], Component::Nand(inputs.iter().map(|i|p_wires[*i]).collect()), state.eval(states));
             },
-            Stmt::Bus(state, output) => {
-                circuit.place_component(p_wires[*output], Component::Bus(vec![]), state.eval(states));
+            Stmt::Xor(state, a, b, o) => {
+                circuit.place_component(p_wires[*o], Component::Xor(p_wires[*a], p_wires[*b]), state.eval(states));
+            },
+            Stmt::Xnor(state, a, b, o) => {
+                circuit.place_component(p_wires[*o], Component::Xnor(p_wires[*a], p_wires[*b]), state.eval(states));
+            },
+            Stmt::Bus(state, mode, output) => {
+                circuit.place_component(p_wires[*output], Component::Bus(*mode, vec![]), state.eval(states));
+            },
+            Stmt::Clock { period, high, phase, output } => {
+                let init = Component::clock_value(*period, *high, *phase, 0);
+                circuit.place_component(p_wires[*output], Component::Clock { period: *period, high: *high, phase: *phase }, init);
+            },
+            Stmt::Mux(data, select, o) => {
+                let wires = |w: &[usize]|w.iter().map(|i|p_wires[*i]).collect();
+                circuit.place_component(p_wires[*o], Component::Mux(wires(data), wires(select)), false);
+            },
+            Stmt::Decoder(select, outputs) => {
+                for (value, o) in outputs.iter().enumerate() {
+                    circuit.place_component(p_wires[*o], Component::Decoder(select.iter().map(|i|p_wires[*i]).collect(), value), false);
+                }
+            },
+            Stmt::Encoder(inputs, outputs) => {
+                for (bit, o) in outputs.iter().enumerate() {
+                    circuit.place_component(p_wires[*o], Component::Encoder(inputs.iter().map(|i|p_wires[*i]).collect(), bit), false);
+                }
+            },
+            Stmt::Rom(words, address, outputs) => {
+                for (bit, o) in outputs.iter().enumerate() {
+                    let table = words.iter().map(|w|w >> bit & 1 == 1).collect();
+                    circuit.place_component(p_wires[*o], Component::Lut(address.iter().map(|i|p_wires[*i]).collect(), table), false);
+                }
+            },
+            Stmt::Ram { words, address, data, write, clock, outputs } => {
+                let wires = |w: &[usize]|w.iter().map(|i|p_wires[*i]).collect::<Box<[usize]>>();
+                let memory = circuit.add_memory(Memory {
+                    address: wires(address),
+                    data: wires(data),
+                    write: p_wires[*write],
+                    clock: clock.map(|c|p_wires[c]),
+                    init: words.clone().into(),
+                });
+                for (bit, o) in outputs.iter().enumerate() {
+                    circuit.place_component(p_wires[*o], Component::Ram(memory, wires(address), bit), false);
+                }
             },
             Stmt::BusInput(bus, a, b) => {
                 circuit.add_bus_input(p_wires[*bus], p_wires[*a], p_wires[*b]);
@@ -154,16 +272,57 @@ impl Stmt {
     }
 }
 
+fn new_wire(next: &mut usize) -> usize {
+    *next += 1;
+    *next - 1
+}
+
+/// Places `a ^ b` on `o` using four NAND gates.
+fn nand_xor(stmts: &mut Vec<Stmt>, next: &mut usize, state: StateAst, a: usize, b: usize, o: usize) {
+    let (n, p, q) = (new_wire(next), new_wire(next), new_wire(next));
+    stmts.push(Stmt::Nand(true.into(), vec![a, b], n));
+    stmts.push(Stmt::Nand(true.into(), vec![a, n], p));
+    stmts.push(Stmt::Nand(true.into(), vec![b, n], q));
+    stmts.push(Stmt::Nand(state, vec![p, q], o));
+}
+
 impl Func {
+    /// Replaces every `xor` and `xnor` gate with the equivalent NAND gates,
+    /// so gate counts and delays match a NAND-only implementation.
+    pub fn expand_xor(&mut self, sign: &FuncSign) {
+        let ports = sign.input + sign.output;
+        let mut next = ports + self.local;
+        for stmt in std::mem::take(&mut self.stmts) {
+            match stmt {
+                Stmt::Xor(state, a, b, o) => nand_xor(&mut self.stmts, &mut next, state, a, b, o),
+                Stmt::Xnor(state, a, b, o) => {
+                    let x = new_wire(&mut next);
+                    nand_xor(&mut self.stmts, &mut next, false.into(), a, b, x);
+                    self.stmts.push(Stmt::Nand(state, vec![x], o));
+                },
+                stmt => self.stmts.push(stmt),
+            }
+        }
+        self.local = next - ports;
+    }
     fn call(&self, circuit: &mut Builder, funcs: &[Func], p_state: &[bool], p_wires: &[usize]) {
+        circuit.enter(&self.name);
+        self.body(circuit, funcs, p_state, p_wires);
+        circuit.exit();
+    }
+    fn body(&self, circuit: &mut Builder, funcs: &[Func], p_state: &[bool], p_wires: &[usize]) {
         let state = p_state;
         let wires = p_wires.iter().copied()
             .chain(std::iter::repeat_with(||circuit.new_slot()).take(self.local))
             .collect::<Vec<_>>();
+        for (name, wire) in &self.names {
+            circuit.name_wire(wires[*wire], name.clone());
+        }
         self.stmts.iter().for_each(|stmt|stmt.build(circuit, funcs, state, &wires))
     }
     pub fn build_circuit(&self, funcs: &[Func], sign: &FuncSign) -> Circuit {
         let mut circuit = Circuit::builder();
+        circuit.set_name(&self.name);
         let mut wires = Vec::with_capacity(sign.input + sign.output);
         wires.extend(std::iter::repeat_with(||{
             let wire = circuit.new_slot();
@@ -175,7 +334,7 @@ impl Func {
             circuit.add_output(wire);
             wire
         }).take(sign.output));
-        self.call(&mut circuit, funcs, &sign.state, &wires);
+        self.body(&mut circuit, funcs, &sign.state, &wires);
         circuit.build()
     }
 }
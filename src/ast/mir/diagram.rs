@@ -0,0 +1,192 @@
+use super::{ Func, FuncSign, Stmt };
+use std::collections::BTreeMap;
+use std::io::{ self, Write };
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Block {
+    Input,
+    Output,
+    Logic,
+    Call(usize),
+}
+
+impl Stmt {
+    fn is_gate(&self) -> bool {
+        match self {
+            Stmt::Call { .. } | Stmt::Source(..) | Stmt::Bus(..) | Stmt::Clock { .. } | Stmt::BusInput(..) => false,
+            Stmt::Buffer(..) | Stmt::Inverter(..) | Stmt::Or(..) | Stmt::And(..) | Stmt::Nor(..) | Stmt::Nand(..) | Stmt::Xor(..) | Stmt::Xnor(..)
+            | Stmt::Mux(..) | Stmt::Decoder(..) | Stmt::Encoder(..) | Stmt::Rom(..) | Stmt::Ram { .. } => true,
+        }
+    }
+    pub(super) fn io(&self, signs: &[&FuncSign]) -> (Vec<usize>, Vec<usize>) {
+        match self {
+            Stmt::Call { func, wires, .. } => {
+                let (input, output) = wires.split_at(signs[*func].input);
+                (input.to_vec(), output.to_vec())
+            },
+            Stmt::Source(_, o) | Stmt::Bus(_, _, o) | Stmt::Clock { output: o, .. } => (vec![], vec![*o]),
+            Stmt::Buffer(_, i, o) | Stmt::Inverter(_, i, o) => (vec![*i], vec![*o]),
+            Stmt::Or(_, inputs, o) | Stmt::And(_, inputs, o) | Stmt::Nor(_, inputs, o) | Stmt::Nand(_, inputs, o) => (inputs.clone(), vec![*o]),
+            Stmt::Xor(_, a, b, o) | Stmt::Xnor(_, a, b, o) => (vec![*a, *b], vec![*o]),
+            Stmt::Mux(data, select, o) => (data.iter().chain(select).copied().collect(), vec![*o]),
+            Stmt::Decoder(input, output) | Stmt::Encoder(input, output) => (input.clone(), output.clone()),
+            Stmt::Rom(_, address, output) => (address.clone(), output.clone()),
+            Stmt::Ram { address, data, write, clock, outputs, .. } => {
+                (address.iter().chain(data).chain(Some(write)).chain(clock).copied().collect(), outputs.clone())
+            },
+            Stmt::BusInput(bus, high, low) => (vec![*high, *low], vec![*bus]),
+        }
+    }
+}
+
+/// Summary of the call hierarchy below a single function.
+pub struct Hierarchy<'a> {
+    funcs: &'a [Func],
+    signs: Vec<&'a FuncSign>,
+    root: usize,
+    gates: Vec<usize>,
+    instances: Vec<usize>,
+}
+
+impl<'a> Hierarchy<'a> {
+    pub fn new(funcs: &'a [Func], signs: &'a [FuncSign], root: usize) -> Self {
+        let signs: Vec<_> = signs.iter().collect();
+        let mut gates = Vec::with_capacity(funcs.len());
+        for func in funcs {
+            let count = func.stmts.iter().map(|stmt|match stmt {
+                Stmt::Call { func, .. } => gates[*func],
+                stmt => stmt.is_gate() as usize,
+            }).sum();
+            gates.push(count);
+        }
+        let mut instances = vec![0; funcs.len()];
+        instances[root] = 1;
+        for id in (0..=root).rev() {
+            for stmt in &funcs[id].stmts {
+                if let Stmt::Call { func, .. } = stmt {
+                    instances[*func] += instances[id];
+                }
+            }
+        }
+        Hierarchy { funcs, signs, root, gates, instances }
+    }
+    fn calls(&self, id: usize) -> BTreeMap<usize, usize> {
+        let mut calls = BTreeMap::new();
+        for stmt in &self.funcs[id].stmts {
+            if let Stmt::Call { func, .. } = stmt {
+                *calls.entry(*func).or_insert(0) += 1;
+            }
+        }
+        calls
+    }
+    fn write_node<W: Write>(&self, out: &mut W, id: usize, count: usize, prefix: &str, last: Option<bool>) -> io::Result<()> {
+        let sign = self.signs[id];
+        let (branch, indent) = match last {
+            None => ("", String::new()),
+            Some(false) => ("├── ", format!("{}│   ", prefix)),
+            Some(true) => ("└── ", format!("{}    ", prefix)),
+        };
+        write!(out, "{}{}{}", prefix, branch, self.funcs[id].name)?;
+        if count > 1 {
+            write!(out, " x{}", count)?;
+        }
+        writeln!(out, " ({} -> {}) {} gates", sign.input, sign.output, self.gates[id])?;
+        let calls = self.calls(id);
+        let len = calls.len();
+        for (i, (func, count)) in calls.into_iter().enumerate() {
+            self.write_node(out, func, count, &indent, Some(i + 1 == len))?;
+        }
+        Ok(())
+    }
+    /// Writes the call tree followed by per function instance and gate totals.
+    pub fn write_tree<W: Write>(&self, mut out: W) -> io::Result<()> {
+        self.write_node(&mut out, self.root, 1, "", None)?;
+        writeln!(out)?;
+        let width = self.funcs.iter().map(|f|f.name.len()).max().unwrap_or(0).max(8);
+        writeln!(out, "{:width$}  instances  gates  total gates", "function", width = width)?;
+        for id in (0..=self.root).rev().filter(|id|self.instances[*id] > 0) {
+            writeln!(out, "{:width$}  {:>9}  {:>5}  {:>11}", self.funcs[id].name, self.instances[id], self.gates[id], self.instances[id] * self.gates[id], width = width)?;
+        }
+        Ok(())
+    }
+    fn wire_label(&self, id: usize, wires: &[usize]) -> String {
+        match wires {
+            [wire] => self.funcs[id].names.iter().find(|(_, w)|w == wire).map_or_else(||"1".to_owned(), |(name, _)|name.clone()),
+            wires => format!("{}", wires.len()),
+        }
+    }
+    fn write_cluster<W: Write>(&self, out: &mut W, id: usize) -> io::Result<()> {
+        let func = &self.funcs[id];
+        let sign = self.signs[id];
+        let node = |block: Block| match block {
+            Block::Input => format!("f{}_in", id),
+            Block::Output => format!("f{}_out", id),
+            Block::Logic => format!("f{}_logic", id),
+            Block::Call(i) => format!("f{}_call{}", id, i),
+        };
+        writeln!(out, "  subgraph cluster_{} {{", id)?;
+        writeln!(out, "    label=\"{} ({} gates, {} instances)\";", func.name, self.gates[id], self.instances[id])?;
+        writeln!(out, "    {} [label=\"in: {}\", shape=invhouse];", node(Block::Input), sign.input)?;
+        writeln!(out, "    {} [label=\"out: {}\", shape=house];", node(Block::Output), sign.output)?;
+        let mut drivers = BTreeMap::new();
+        for wire in 0..sign.input {
+            drivers.insert(wire, Block::Input);
+        }
+        let mut logic = false;
+        for (i, stmt) in func.stmts.iter().enumerate() {
+            let block = match stmt {
+                Stmt::Call { func: callee, .. } => {
+                    let sign = self.signs[*callee];
+                    writeln!(out, "    {} [label=\"{}\\n{} -> {}\", shape=box];", node(Block::Call(i)), self.funcs[*callee].name, sign.input, sign.output)?;
+                    Block::Call(i)
+                },
+                _ => {
+                    logic = true;
+                    Block::Logic
+                },
+            };
+            for wire in stmt.io(&self.signs).1 {
+                drivers.insert(wire, block);
+            }
+        }
+        if logic {
+            writeln!(out, "    {} [label=\"logic\", shape=ellipse];", node(Block::Logic))?;
+        }
+        writeln!(out, "  }}")?;
+        let mut edges: BTreeMap<(Block, Block), Vec<usize>> = BTreeMap::new();
+        for (i, stmt) in func.stmts.iter().enumerate() {
+            let block = if let Stmt::Call { .. } = stmt { Block::Call(i) } else { Block::Logic };
+            for wire in stmt.io(&self.signs).0 {
+                if let Some(driver) = drivers.get(&wire) {
+                    if *driver != block || block != Block::Logic {
+                        edges.entry((*driver, block)).or_default().push(wire);
+                    }
+                }
+            }
+        }
+        for wire in sign.input..sign.input + sign.output {
+            if let Some(driver) = drivers.get(&wire) {
+                edges.entry((*driver, Block::Output)).or_default().push(wire);
+            }
+        }
+        for ((from, to), wires) in edges {
+            writeln!(out, "  {} -> {} [label=\"{}\"];", node(from), node(to), self.wire_label(id, &wires))?;
+        }
+        for (i, stmt) in func.stmts.iter().enumerate() {
+            if let Stmt::Call { func: callee, .. } = stmt {
+                writeln!(out, "  {} -> f{}_in [lhead=cluster_{}, style=dashed];", node(Block::Call(i)), callee, callee)?;
+            }
+        }
+        Ok(())
+    }
+    /// Writes one cluster per reachable function with the wiring between its calls, and dashed edges from every call to the function it instantiates.
+    pub fn write_dot<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "digraph \"{}\" {{", self.funcs[self.root].name)?;
+        writeln!(out, "  compound=true;")?;
+        writeln!(out, "  rankdir=LR;")?;
+        for id in (0..=self.root).rev().filter(|id|self.instances[*id] > 0) {
+            self.write_cluster(&mut out, id)?;
+        }
+        writeln!(out, "}}")
+    }
+}
@@ -0,0 +1,359 @@
+//! The JSON dump of a compiled design.
+//!
+//! A dump is an object with the fields
+//! - `format`: always `"circuit-sim"`, and `version`: currently `2`.
+//! - `top`: the name of the function the netlist was built from, and `root`: its index in `funcs`,
+//!   as functions from different files may share a name.
+//!
+//! Version 1 dumps, which have no `root` and no statements added since, are still read, with the
+//! root found by its name.
+//! - `funcs`: every function in definition order, so calls refer to them by index. Each has
+//!   a `name`, its `state` as `[name, default]` pairs, `input` and `output` port counts,
+//!   the number of `local` wires, wire `names` as `[name, wire]` pairs and its `stmts`.
+//!   Wires are numbered inputs first, then outputs, then locals.
+//! - `netlist`: the flattened circuit, as described in `circuit_sim::netlist`.
+//!
+//! Statements are objects with an `op` of `call`, `source`, `buffer`, `not`, `or`, `and`, `nor`,
+//! `nand`, `xor`, `xnor`, `mux`, `decoder`, `encoder`, `rom`, `ram`, `bus`, `clock` or `bus_input`. Calls have `func`,
+//! `state` and `wires` (inputs, then outputs), bus inputs have `bus`, `high` and `low`, clocks have
+//! `period`, `high`, `phase` and `output`, muxes have `inputs`, `select` and `output`, decoders have
+//! `select` and `outputs`, buses have a `mode` as in the netlist, encoders have `inputs` and `outputs`, ROMs have their `words` as hex strings,
+//! `address` and `outputs`, RAMs have their initial `words`, `address`, `data`, `write`, `clock`
+//! (null if none) and `outputs`, and the rest have a `state` and an `output`,
+//! with gates also listing their `inputs`. A state is `{"negate": bool, "const": bool}`
+//! or `{"negate": bool, "param": index}`.
+use super::{ Func, FuncSign, Stmt, StateAst, StateRef };
+use circuit_sim::base::{ BusMode, Memory };
+use circuit_sim::circuit::Circuit;
+use circuit_sim::json::Json;
+
+const VERSION: usize = 2;
+
+fn state_to_json(state: &StateAst) -> Json {
+    let value = match state.state {
+        StateRef::Const(b) => ("const", b.into()),
+        StateRef::Ident(i) => ("param", i.into()),
+    };
+    Json::object(vec![("negate", state.negate.into()), value])
+}
+
+fn gate(op: &str, state: &StateAst, inputs: Vec<usize>, output: usize) -> Json {
+    Json::object(vec![("op", op.into()), ("state", state_to_json(state)), ("inputs", inputs.into()), ("output", output.into())])
+}
+
+fn stmt_to_json(stmt: &Stmt) -> Json {
+    match stmt {
+        Stmt::Call { func, state, wires } => Json::object(vec![
+            ("op", "call".into()),
+            ("func", (*func).into()),
+            ("state", Json::Array(state.iter().map(state_to_json).collect())),
+            ("wires", wires.clone().into()),
+        ]),
+        Stmt::Source(state, o) => Json::object(vec![("op", "source".into()), ("state", state_to_json(state)), ("output", (*o).into())]),
+        Stmt::Bus(state, mode, o) => Json::object(vec![("op", "bus".into()), ("mode", mode.name().into()), ("state", state_to_json(state)), ("output", (*o).into())]),
+        Stmt::Buffer(state, a, o) => gate("buffer", state, vec![*a], *o),
+        Stmt::Inverter(state, a, o) => gate("not", state, vec![*a], *o),
+        Stmt::Or(state, inputs, o) => gate("or", state, inputs.clone(), *o),
+        Stmt::And(state, inputs, o) => gate("and", state, inputs.clone(), *o),
+        Stmt::Nor(state, inputs, o) => gate("nor", state, inputs.clone(), *o),
+        Stmt::Nand(state, inputs, o) => gate("nand", state, inputs.clone(), *o),
+        Stmt::Xor(state, a, b, o) => gate("xor", state, vec![*a, *b], *o),
+        Stmt::Xnor(state, a, b, o) => gate("xnor", state, vec![*a, *b], *o),
+        Stmt::Clock { period, high, phase, output } => Json::object(vec![
+            ("op", "clock".into()),
+            ("period", (*period).into()),
+            ("high", (*high).into()),
+            ("phase", (*phase).into()),
+            ("output", (*output).into()),
+        ]),
+        Stmt::Mux(data, select, o) => Json::object(vec![("op", "mux".into()), ("inputs", data.clone().into()), ("select", select.clone().into()), ("output", (*o).into())]),
+        Stmt::Decoder(select, outputs) => Json::object(vec![("op", "decoder".into()), ("select", select.clone().into()), ("outputs", outputs.clone().into())]),
+        Stmt::Encoder(inputs, outputs) => Json::object(vec![("op", "encoder".into()), ("inputs", inputs.clone().into()), ("outputs", outputs.clone().into())]),
+        Stmt::Rom(words, address, outputs) => Json::object(vec![
+            ("op", "rom".into()),
+            ("words", Json::Array(words.iter().map(|w|format!("{:#x}", w).into()).collect())),
+            ("address", address.clone().into()),
+            ("outputs", outputs.clone().into()),
+        ]),
+        Stmt::Ram { words, address, data, write, clock, outputs } => Json::object(vec![
+            ("op", "ram".into()),
+            ("words", Json::Array(words.iter().map(|w|format!("{:#x}", w).into()).collect())),
+            ("address", address.clone().into()),
+            ("data", data.clone().into()),
+            ("write", (*write).into()),
+            ("clock", (*clock).into()),
+            ("outputs", outputs.clone().into()),
+        ]),
+        Stmt::BusInput(bus, high, low) => Json::object(vec![("op", "bus_input".into()), ("bus", (*bus).into()), ("high", (*high).into()), ("low", (*low).into())]),
+    }
+}
+
+fn usize_field(json: &Json, key: &str) -> Result<usize, String> {
+    json.field(key)?.as_usize().ok_or_else(||format!("{} must be a number", key))
+}
+
+fn array_field<'a>(json: &'a Json, key: &str) -> Result<&'a [Json], String> {
+    json.field(key)?.as_array().ok_or_else(||format!("{} must be an array", key))
+}
+
+fn wire_field(json: &Json, key: &str, wires: usize) -> Result<usize, String> {
+    Some(usize_field(json, key)?).filter(|w|*w < wires).ok_or_else(||format!("{} is not a wire", key))
+}
+
+fn wires_field(json: &Json, key: &str, wires: usize) -> Result<Vec<usize>, String> {
+    array_field(json, key)?.iter().map(|w|w.as_usize().filter(|w|*w < wires).ok_or_else(||format!("Invalid wire in {}: {}", key, w))).collect()
+}
+
+fn words_field(json: &Json) -> Result<Vec<u64>, String> {
+    array_field(json, "words")?.iter()
+        .map(|w|w.as_str().and_then(|w|u64::from_str_radix(w.strip_prefix("0x")?, 16).ok()).ok_or_else(||format!("Invalid word: {}", w)))
+        .collect()
+}
+
+fn state_from_json(json: &Json, states: usize) -> Result<StateAst, String> {
+    let negate = json.field("negate")?.as_bool().ok_or("negate must be a boolean")?;
+    let state = match (json.get("const"), json.get("param")) {
+        (Some(b), None) => StateRef::Const(b.as_bool().ok_or("const must be a boolean")?),
+        (None, Some(i)) => StateRef::Ident(i.as_usize().filter(|i|*i < states).ok_or_else(||format!("Invalid state parameter: {}", i))?),
+        _ => return Err(format!("Invalid state: {}", json)),
+    };
+    Ok(StateAst { negate, state })
+}
+
+fn stmt_from_json(json: &Json, signs: &[FuncSign], states: usize, wires: usize) -> Result<Stmt, String> {
+    let op = json.field("op")?.as_str().ok_or("op must be a string")?;
+    Ok(match op {
+        "call" => {
+            let func = Some(usize_field(json, "func")?).filter(|f|*f < signs.len()).ok_or("Calls may only refer to earlier functions")?;
+            let state = array_field(json, "state")?.iter().map(|s|state_from_json(s, states)).collect::<Result<Vec<_>, _>>()?;
+            let call_wires = wires_field(json, "wires", wires)?;
+            let sign = &signs[func];
+            if state.len() != sign.state.len() || call_wires.len() != sign.input + sign.output {
+                return Err(format!("The call to function {} does not match its signature", func));
+            }
+            Stmt::Call { func, state, wires: call_wires }
+        },
+        "bus_input" => Stmt::BusInput(wire_field(json, "bus", wires)?, wire_field(json, "high", wires)?, wire_field(json, "low", wires)?),
+        "mux" => Stmt::Mux(wires_field(json, "inputs", wires)?, wires_field(json, "select", wires)?, wire_field(json, "output", wires)?),
+        "decoder" => Stmt::Decoder(wires_field(json, "select", wires)?, wires_field(json, "outputs", wires)?),
+        "encoder" => Stmt::Encoder(wires_field(json, "inputs", wires)?, wires_field(json, "outputs", wires)?),
+        "rom" => {
+            let outputs = wires_field(json, "outputs", wires)?;
+            if outputs.is_empty() || outputs.len() > 64 {
+                return Err(format!("Invalid rom: {}", json));
+            }
+            Stmt::Rom(words_field(json)?, wires_field(json, "address", wires)?, outputs)
+        },
+        "ram" => {
+            let (address, data, outputs) = (wires_field(json, "address", wires)?, wires_field(json, "data", wires)?, wires_field(json, "outputs", wires)?);
+            let words = words_field(json)?;
+            if outputs.is_empty() || outputs.len() > 64 || data.len() != outputs.len() || address.len() > Memory::MAX_ADDRESS || words.len() > 1 << address.len() {
+                return Err(format!("Invalid ram: {}", json));
+            }
+            let clock = match json.field("clock")? {
+                Json::Null => None,
+                _ => Some(wire_field(json, "clock", wires)?),
+            };
+            Stmt::Ram { words, address, data, write: wire_field(json, "write", wires)?, clock, outputs }
+        },
+        "clock" => {
+            let (period, high, phase) = (usize_field(json, "period")?, usize_field(json, "high")?, usize_field(json, "phase")?);
+            if period == 0 || high > period {
+                return Err(format!("Invalid clock: {}", json));
+            }
+            Stmt::Clock { period, high, phase, output: wire_field(json, "output", wires)? }
+        },
+        op => {
+            let state = state_from_json(json.field("state")?, states)?;
+            let o = wire_field(json, "output", wires)?;
+            let inputs = if op == "source" || op == "bus" { vec![] } else { wires_field(json, "inputs", wires)? };
+            match (op, &inputs[..]) {
+                ("source", []) => Stmt::Source(state, o),
+                ("bus", []) => {
+                    let mode = match json.get("mode") {
+                        None => BusMode::Float,
+                        Some(mode) => mode.as_str().and_then(BusMode::parse).ok_or_else(||format!("Unknown bus mode: {}", mode))?,
+                    };
+                    Stmt::Bus(state, mode, o)
+                },
+                ("buffer", &[a]) => Stmt::Buffer(state, a, o),
+                ("not", &[a]) => Stmt::Inverter(state, a, o),
+                ("or", inputs) if !inputs.is_empty() => Stmt::Or(state, inputs.to_vec(), o),
+                ("and", inputs) if !inputs.is_empty() => Stmt::And(state, inputs.to_vec(), o),
+                ("nor", inputs) if !inputs.is_empty() => Stmt::Nor(state, inputs.to_vec(), o),
+                ("nand", inputs) if !inputs.is_empty() => Stmt::Nand(state, inputs.to_vec(), o),
+                ("xor", &[a, b]) => Stmt::Xor(state, a, b, o),
+                ("xnor", &[a, b]) => Stmt::Xnor(state, a, b, o),
+                (op, inputs) => return Err(format!("Invalid statement: {} with {} inputs", op, inputs.len())),
+            }
+        },
+    })
+}
+
+fn func_from_json(json: &Json, id: usize, signs: &[FuncSign]) -> Result<(FuncSign, Func), String> {
+    let name = json.field("name")?.as_str().ok_or("name must be a string")?.to_owned();
+    let (states, state) = array_field(json, "state")?.iter().map(|pair|match pair.as_array() {
+        Some([name, default]) => match (name.as_str(), default.as_bool()) {
+            (Some(name), Some(default)) => Ok((name.to_owned(), default)),
+            _ => Err(format!("Invalid state: {}", pair)),
+        },
+        _ => Err(format!("Invalid state: {}", pair)),
+    }).collect::<Result<Vec<_>, String>>()?.into_iter().unzip();
+    let input = usize_field(json, "input")?;
+    let output = usize_field(json, "output")?;
+    let local = usize_field(json, "local")?;
+    let wires = input + output + local;
+    let names = array_field(json, "names")?.iter().map(|pair|match pair.as_array() {
+        Some([name, wire]) => match (name.as_str(), wire.as_usize().filter(|w|*w < wires)) {
+            (Some(name), Some(wire)) => Ok((name.to_owned(), wire)),
+            _ => Err(format!("Invalid name: {}", pair)),
+        },
+        _ => Err(format!("Invalid name: {}", pair)),
+    }).collect::<Result<_, String>>()?;
+    let sign = FuncSign { id, state, input, output };
+    let stmts = array_field(json, "stmts")?.iter()
+        .map(|s|stmt_from_json(s, signs, sign.state.len(), wires))
+        .collect::<Result<_, _>>()
+        .map_err(|e|format!("In {}: {}", name, e))?;
+    Ok((sign, Func { name, states, names, local, stmts }))
+}
+
+/// Writes the functions together with the circuit built from `root`.
+pub fn write_dump(funcs: &[Func], signs: &[FuncSign], root: usize, circuit: &Circuit) -> Json {
+    Json::object(vec![
+        ("format", "circuit-sim".into()),
+        ("version", VERSION.into()),
+        ("top", funcs[root].name.as_str().into()),
+        ("root", root.into()),
+        ("funcs", Json::Array(funcs.iter().zip(signs).map(|(func, sign)|{
+            Json::object(vec![
+                ("name", func.name.as_str().into()),
+                ("state", Json::Array(func.states.iter().zip(&sign.state).map(|(name, b)|Json::Array(vec![name.as_str().into(), (*b).into()])).collect())),
+                ("input", sign.input.into()),
+                ("output", sign.output.into()),
+                ("local", func.local.into()),
+                ("names", Json::Array(func.names.iter().map(|(name, wire)|Json::Array(vec![name.as_str().into(), (*wire).into()])).collect())),
+                ("stmts", Json::Array(func.stmts.iter().map(stmt_to_json).collect())),
+            ])
+        }).collect())),
+        ("netlist", circuit.to_json()),
+    ])
+}
+
+pub fn is_dump(json: &Json) -> bool {
+    json.get("format").and_then(Json::as_str) == Some("circuit-sim")
+}
+
+/// Reads a dump, returning the functions, their signatures, the index of the top function and its circuit.
+pub fn read_dump(json: &Json) -> Result<(Vec<Func>, Vec<FuncSign>, usize, Circuit), String> {
+    if !is_dump(json) {
+        return Err("Not a circuit-sim dump".to_owned());
+    }
+    let version = usize_field(json, "version")?;
+    if version == 0 || version > VERSION {
+        return Err(format!("Unsupported dump version {}, expected at most {}", version, VERSION));
+    }
+    let mut signs = vec![];
+    let mut funcs = vec![];
+    for (id, func) in array_field(json, "funcs")?.iter().enumerate() {
+        let (sign, func) = func_from_json(func, id, &signs)?;
+        signs.push(sign);
+        funcs.push(func);
+    }
+    let root = if version == 1 {
+        let top = json.field("top")?.as_str().ok_or("top must be a string")?;
+        funcs.iter().position(|f|f.name == top).ok_or_else(||format!("Unknown top function: {}", top))?
+    } else {
+        Some(usize_field(json, "root")?).filter(|r|*r < funcs.len()).ok_or("root must be the index of a function")?
+    };
+    let circuit = Circuit::from_json(json.field("netlist")?)?;
+    Ok((funcs, signs, root, circuit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ read_dump, write_dump };
+    use crate::ast::parse;
+    use circuit_sim::json::Json;
+    use std::path::Path;
+
+    const SRC: &str = "
+cell[s=1](a, b) -> (o) {
+    o = xor[!s](a, b);
+}
+top(a, b, c) -> (o, p, q, r, d0, d1, d2, d3, e0, e1, m, k, x0, x1, y, z) {
+    o = cell[0](a, b);
+    p = and(a, or(b, c), nand(a, b));
+    q = nor(xnor(a, b), not(c));
+    r = buffer(1);
+    (d0, d1, d2, d3) = decoder(a, b);
+    (e0, e1) = encoder(a, b, c, r);
+    m = mux(a, b, c);
+    k = bus[pullup]();
+    bus_input(k, a, b);
+    (x0, x1) = rom[1, 2, 3](a, b);
+    y = ram[1](a, b, c);
+    z = sync_ram[](a, b, c, clock[4]());
+}
+";
+
+    fn dump() -> Json {
+        let program = parse(SRC, Path::new("test.cir")).unwrap();
+        let sign = &program.env["top"];
+        let circuit = program.funcs[sign.id].build_circuit(&program.funcs, sign);
+        write_dump(&program.funcs, &program.signs, sign.id, &circuit)
+    }
+
+    /// `json` with the field `key` set to `value`, or removed without one.
+    fn with(json: &Json, key: &str, value: Option<Json>) -> Json {
+        let mut fields = json.as_object().unwrap().to_vec();
+        fields.retain(|(k, _)|k != key);
+        fields.extend(value.map(|v|(key.to_owned(), v)));
+        Json::Object(fields)
+    }
+
+    /// `json` with the statements of the top function replaced by `stmts`.
+    fn with_stmts(json: &Json, stmts: &str) -> Json {
+        let mut funcs = json.field("funcs").unwrap().as_array().unwrap().to_vec();
+        funcs[1] = with(&funcs[1], "stmts", Some(Json::parse(stmts).unwrap()));
+        with(json, "funcs", Some(Json::Array(funcs)))
+    }
+
+    fn read_err(json: &Json) -> String {
+        read_dump(json).err().unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let json = dump();
+        let (funcs, signs, root, circuit) = read_dump(&Json::parse(&json.to_string()).unwrap()).unwrap();
+        assert_eq!((funcs[root].name.as_str(), root), ("top", 1));
+        assert_eq!(write_dump(&funcs, &signs, root, &circuit), json);
+    }
+
+    #[test]
+    fn version_1() {
+        let json = with(&with(&dump(), "version", Some(1.into())), "root", None);
+        let (funcs, _, root, _) = read_dump(&json).unwrap();
+        assert_eq!(funcs[root].name, "top");
+        assert_eq!(read_err(&with(&json, "top", Some("none".into()))), "Unknown top function: none");
+    }
+
+    #[test]
+    fn errors() {
+        let json = dump();
+        assert_eq!(read_err(&with(&json, "format", None)), "Not a circuit-sim dump");
+        assert_eq!(read_err(&with(&json, "version", Some(3.into()))), "Unsupported dump version 3, expected at most 2");
+        assert_eq!(read_err(&with(&json, "root", Some(2.into()))), "root must be the index of a function");
+        let call = |func: usize, wires: &str|with_stmts(&json, &format!(r#"[{{"op": "call", "func": {}, "state": [], "wires": {}}}]"#, func, wires));
+        assert_eq!(read_err(&call(1, "[0, 1, 2]")), "In top: Calls may only refer to earlier functions");
+        assert_eq!(read_err(&call(0, "[0, 1, 2]")), "In top: The call to function 0 does not match its signature");
+        assert_eq!(read_err(&call(0, "[0, 99]")), "In top: Invalid wire in wires: 99");
+        let gate = |op: &str, state: &str|with_stmts(&json, &format!(r#"[{{"op": "{}", "state": {}, "inputs": [0], "output": 3}}]"#, op, state));
+        assert_eq!(read_err(&gate("not", r#"{"negate": false, "param": 0}"#)), "In top: Invalid state parameter: 0");
+        assert_eq!(read_err(&gate("not", r#"{"negate": false}"#)), r#"In top: Invalid state: {"negate":false}"#);
+        assert_eq!(read_err(&gate("xor", r#"{"negate": false, "const": true}"#)), "In top: Invalid statement: xor with 1 inputs");
+        let clock = with_stmts(&json, r#"[{"op": "clock", "period": 2, "high": 3, "phase": 0, "output": 3}]"#);
+        assert!(read_err(&clock).starts_with("In top: Invalid clock: "));
+    }
+}
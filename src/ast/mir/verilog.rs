@@ -0,0 +1,250 @@
+use super::{ Func, FuncSign, Stmt, StateAst, StateRef };
+use circuit_sim::base::BusMode;
+use std::io::{ self, Write };
+
+const KEYWORDS: &[&str] = &[
+    "always", "and", "assign", "begin", "buf", "bufif0", "bufif1", "case", "default", "else", "end", "for", "function",
+    "if", "initial", "inout", "input", "integer", "module", "nand", "nor", "not", "notif0", "notif1", "or", "output",
+    "parameter", "pulldown", "pullup", "reg", "supply0", "supply1", "task", "tri", "tri0", "tri1", "wand", "wire", "wor",
+    "xnor", "xor",
+];
+
+fn ident(name: &str) -> String {
+    if KEYWORDS.contains(&name) {
+        format!("\\{} ", name)
+    } else {
+        name.to_owned()
+    }
+}
+
+/// The value of a state given the values of the function's own state.
+fn fold(values: &[bool], state: &StateAst) -> bool {
+    let value = match state.state {
+        StateRef::Const(b) => b,
+        StateRef::Ident(i) => values[i],
+    };
+    value != state.negate
+}
+
+/// The name of the module for a function with the given state, suffixed with the state unless it is the default,
+/// and with the function's index if another function has the same name, as imported files may.
+fn module_name(funcs: &[Func], id: usize, sign: &FuncSign, state: &[bool]) -> String {
+    let func = &funcs[id];
+    let mut name = func.name.clone();
+    if funcs.iter().filter(|f|f.name == func.name).count() > 1 {
+        name = format!("{}${}", name, id);
+    }
+    if state != sign.state.as_slice() {
+        name.push('$');
+        name.extend(state.iter().map(|b|if *b { '1' } else { '0' }));
+    }
+    if name == func.name { ident(&name) } else { name }
+}
+
+/// A function with its state folded in, as init attributes only hold constants.
+struct Module<'a> {
+    id: usize,
+    func: &'a Func,
+    sign: &'a FuncSign,
+    inout: &'a [bool],
+    state: &'a [bool],
+}
+
+impl<'a> Module<'a> {
+    fn net(&self, wire: usize) -> String {
+        match self.func.names.iter().find(|(_, w)|*w == wire) {
+            Some((name, _)) => ident(name),
+            None => format!("n${}", wire),
+        }
+    }
+    /// Concatenates wires given least significant first.
+    fn concat(&self, wires: &[usize]) -> String {
+        let nets: Vec<_> = wires.iter().rev().map(|w|self.net(*w)).collect();
+        format!("{{{}}}", nets.join(", "))
+    }
+    fn write_gate<W: Write>(&self, out: &mut W, gate: &str, i: usize, inputs: &[usize], output: usize) -> io::Result<()> {
+        write!(out, "  {} g{} ({}", gate, i, self.net(output))?;
+        for input in inputs {
+            write!(out, ", {}", self.net(*input))?;
+        }
+        writeln!(out, ");")
+    }
+    fn write<W: Write>(&self, out: &mut W, funcs: &[Func], signs: &[&FuncSign], inouts: &[Vec<bool>]) -> io::Result<()> {
+        let func = self.func;
+        let ports = self.sign.input + self.sign.output;
+        write!(out, "module {}(", module_name(funcs, self.id, self.sign, self.state))?;
+        for wire in 0..ports {
+            if wire > 0 {
+                write!(out, ", ")?;
+            }
+            write!(out, "{}", self.net(wire))?;
+        }
+        writeln!(out, ");")?;
+        for wire in 0..ports {
+            let dir = if self.inout[wire] { "inout" } else if wire < self.sign.input { "input" } else { "output" };
+            writeln!(out, "  {} {};", dir, self.net(wire))?;
+        }
+        let mut inits = vec![None; ports + func.local];
+        for stmt in &func.stmts {
+            match stmt {
+                Stmt::Call { .. } | Stmt::BusInput(..) | Stmt::Source(..) | Stmt::Clock { .. }
+                | Stmt::Mux(..) | Stmt::Decoder(..) | Stmt::Encoder(..) | Stmt::Rom(..) | Stmt::Ram { .. } => {},
+                Stmt::Buffer(state, _, o) | Stmt::Inverter(state, _, o) | Stmt::Bus(state, _, o)
+                | Stmt::Or(state, _, o) | Stmt::And(state, _, o) | Stmt::Nor(state, _, o) | Stmt::Nand(state, _, o)
+                | Stmt::Xor(state, _, _, o) | Stmt::Xnor(state, _, _, o) => {
+                    if fold(self.state, state) {
+                        inits[*o] = Some("1'b1");
+                    }
+                },
+            }
+        }
+        for (wire, init) in inits.iter().enumerate() {
+            let kind = match func.stmts.iter().find(|s|matches!(s, Stmt::Bus(_, _, o) | Stmt::Clock { output: o, .. } if *o == wire)) {
+                Some(Stmt::Bus(_, BusMode::Float, _)) => "tri",
+                Some(Stmt::Bus(_, BusMode::PullUp, _)) => "tri1",
+                Some(Stmt::Bus(_, BusMode::PullDown, _)) => "tri0",
+                // High drivers of an open drain bus only matter while nothing pulls it low, when it reads 1 anyway.
+                Some(Stmt::Bus(_, BusMode::OpenDrain, _)) => "wand",
+                Some(_) => "reg",
+                None => "wire",
+            };
+            match init {
+                Some(init) => writeln!(out, "  (* init = {} *) {} {};", init, kind, self.net(wire))?,
+                None if wire >= self.sign.input => writeln!(out, "  {} {};", kind, self.net(wire))?,
+                None => {},
+            }
+        }
+        for (i, stmt) in func.stmts.iter().enumerate() {
+            match stmt {
+                Stmt::Call { func: id, state, wires } => {
+                    let callee = &funcs[*id];
+                    let state: Vec<_> = state.iter().map(|s|fold(self.state, s)).collect();
+                    write!(out, "  {} u{} (", module_name(funcs, *id, signs[*id], &state), i)?;
+                    let callee_module = Module { id: *id, func: callee, sign: signs[*id], inout: &inouts[*id], state: &state };
+                    for (j, wire) in wires.iter().enumerate() {
+                        if j > 0 {
+                            write!(out, ", ")?;
+                        }
+                        write!(out, ".{}({})", callee_module.net(j), self.net(*wire))?;
+                    }
+                    writeln!(out, ");")?;
+                },
+                Stmt::Source(state, o) => writeln!(out, "  assign {} = 1'b{};", self.net(*o), fold(self.state, state) as u8)?,
+                Stmt::Buffer(_, a, o) => writeln!(out, "  buf g{} ({}, {});", i, self.net(*o), self.net(*a))?,
+                Stmt::Inverter(_, a, o) => writeln!(out, "  not g{} ({}, {});", i, self.net(*o), self.net(*a))?,
+                Stmt::Or(_, inputs, o) => self.write_gate(out, "or", i, inputs, *o)?,
+                Stmt::And(_, inputs, o) => self.write_gate(out, "and", i, inputs, *o)?,
+                Stmt::Nor(_, inputs, o) => self.write_gate(out, "nor", i, inputs, *o)?,
+                Stmt::Nand(_, inputs, o) => self.write_gate(out, "nand", i, inputs, *o)?,
+                Stmt::Xor(_, a, b, o) => writeln!(out, "  xor g{} ({}, {}, {});", i, self.net(*o), self.net(*a), self.net(*b))?,
+                Stmt::Xnor(_, a, b, o) => writeln!(out, "  xnor g{} ({}, {}, {});", i, self.net(*o), self.net(*a), self.net(*b))?,
+                Stmt::Bus(_, BusMode::OpenDrain, o) => writeln!(out, "  pullup g{} ({});", i, self.net(*o))?,
+                Stmt::Bus(..) => {},
+                Stmt::Mux(data, select, o) => {
+                    // Shifting past the data gives 0, like the simulator.
+                    writeln!(out, "  assign {} = {} >> {};", self.net(*o), self.concat(data), self.concat(select))?;
+                },
+                Stmt::Decoder(select, outputs) => for (value, o) in outputs.iter().enumerate() {
+                    writeln!(out, "  assign {} = {} == {};", self.net(*o), self.concat(select), value)?;
+                },
+                Stmt::Encoder(inputs, outputs) => for (bit, o) in outputs.iter().enumerate() {
+                    write!(out, "  assign {} =", self.net(*o))?;
+                    for (i, input) in inputs.iter().enumerate().rev() {
+                        write!(out, " {} ? 1'b{} :", self.net(*input), i >> bit & 1)?;
+                    }
+                    writeln!(out, " 1'b0;")?;
+                },
+                Stmt::Rom(words, address, outputs) => {
+                    writeln!(out, "  reg [{}:0] g{}_rom [0:{}];", outputs.len() - 1, i, words.len().max(1) - 1)?;
+                    writeln!(out, "  initial begin")?;
+                    for (j, word) in words.iter().enumerate() {
+                        writeln!(out, "    g{}_rom[{}] = {}'h{:x};", i, j, outputs.len(), word)?;
+                    }
+                    writeln!(out, "  end")?;
+                    // Reading past the last word gives 0, like the simulator.
+                    let address = self.concat(address);
+                    writeln!(out, "  assign {} = {} < {} ? g{}_rom[{}] : 0;", self.concat(outputs), address, words.len(), i, address)?;
+                },
+                Stmt::Ram { words, address, data, write, clock, outputs } => {
+                    writeln!(out, "  reg [{}:0] g{}_ram [0:{}];", outputs.len() - 1, i, (1 << address.len()) - 1)?;
+                    writeln!(out, "  integer g{}_word;", i)?;
+                    writeln!(out, "  initial begin")?;
+                    writeln!(out, "    for (g{0}_word = 0; g{0}_word < {1}; g{0}_word = g{0}_word + 1) g{0}_ram[g{0}_word] = 0;", i, 1 << address.len())?;
+                    for (j, word) in words.iter().enumerate() {
+                        writeln!(out, "    g{}_ram[{}] = {}'h{:x};", i, j, outputs.len(), word)?;
+                    }
+                    writeln!(out, "  end")?;
+                    let address = self.concat(address);
+                    match clock {
+                        Some(clock) => writeln!(out, "  always @(posedge {}) if ({}) g{}_ram[{}] <= {};", self.net(*clock), self.net(*write), i, address, self.concat(data))?,
+                        None => writeln!(out, "  always @* if ({}) g{}_ram[{}] = {};", self.net(*write), i, address, self.concat(data))?,
+                    }
+                    writeln!(out, "  assign {} = g{}_ram[{}];", self.concat(outputs), i, address)?;
+                },
+                Stmt::Clock { period, high, phase, output } => {
+                    // Clocks are behavioural, counting unit delays like the simulator does.
+                    writeln!(out, "  integer g{}_step = 0;", i)?;
+                    writeln!(out, "  initial {} = {};", self.net(*output), (*phase % period < *high) as u8)?;
+                    writeln!(out, "  always #1 begin")?;
+                    writeln!(out, "    g{}_step = g{}_step + 1;", i, i)?;
+                    writeln!(out, "    {} = (g{}_step + {}) % {} < {};", self.net(*output), i, phase, period, high)?;
+                    writeln!(out, "  end")?;
+                },
+                Stmt::BusInput(bus, high, low) => {
+                    writeln!(out, "  bufif1 g{}h ({}, 1'b1, {});", i, self.net(*bus), self.net(*high))?;
+                    writeln!(out, "  bufif1 g{}l ({}, 1'b0, {});", i, self.net(*bus), self.net(*low))?;
+                },
+            }
+        }
+        writeln!(out, "endmodule")
+    }
+}
+
+/// Writes one structural Verilog module for the given function and for every function it calls.
+/// Function state is folded into the modules, with one module for each state a function is called with,
+/// and non zero initial gate values become `init` attributes.
+pub fn write_verilog<W: Write>(funcs: &[Func], signs: &[FuncSign], root: usize, mut out: W) -> io::Result<()> {
+    let signs: Vec<_> = signs.iter().collect();
+    let mut modules = vec![(root, signs[root].state.clone())];
+    let mut next = 0;
+    while next < modules.len() {
+        let (id, values) = modules[next].clone();
+        for stmt in &funcs[id].stmts {
+            if let Stmt::Call { func, state, .. } = stmt {
+                let callee = (*func, state.iter().map(|s|fold(&values, s)).collect());
+                if !modules.contains(&callee) {
+                    modules.push(callee);
+                }
+            }
+        }
+        next += 1;
+    }
+    modules.sort();
+    let mut inouts: Vec<Vec<bool>> = Vec::with_capacity(funcs.len());
+    for (func, sign) in funcs.iter().zip(&signs) {
+        let mut inout = vec![false; sign.input + sign.output];
+        for stmt in &func.stmts {
+            match stmt {
+                Stmt::BusInput(bus, _, _) if *bus < inout.len() => inout[*bus] = true,
+                Stmt::Call { func, wires, .. } => {
+                    for (wire, callee_inout) in wires.iter().zip(&inouts[*func]) {
+                        if *callee_inout && *wire < inout.len() {
+                            inout[*wire] = true;
+                        }
+                    }
+                },
+                _ => {},
+            }
+        }
+        inouts.push(inout);
+    }
+    let mut first = true;
+    for (id, state) in &modules {
+        if !first {
+            writeln!(out)?;
+        }
+        first = false;
+        Module { id: *id, func: &funcs[*id], sign: signs[*id], inout: &inouts[*id], state }.write(&mut out, funcs, &signs, &inouts)?;
+    }
+    Ok(())
+}
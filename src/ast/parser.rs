@@ -1,4 +1,4 @@
-use super::hir::{ StateAst, Ast, Stmt, Func };
+use super::hir::{ StateAst, Op, Ast, Stmt, Func, Item };
 use pest_derive::Parser;
 use pest::Parser;
 type Pair<'i> = pest::iterators::Pair<'i, Rule>;
@@ -7,8 +7,17 @@ type Pair<'i> = pest::iterators::Pair<'i, Rule>;
 #[grammar = "ast/cir.pest"]
 struct CirParser;
 
-pub fn parse<'a>(s: &'a str) -> Result<impl Iterator<Item = (String, Func)> + 'a, String> {
-    CirParser::parse(Rule::file, s).map_err(|e|format!("{}", e)).map(|pairs|pairs.filter(|pair|pair.as_rule() != Rule::EOI).map(<(String, Func)>::parse))
+pub fn parse<'a>(s: &'a str) -> Result<impl Iterator<Item = Item> + 'a, String> {
+    CirParser::parse(Rule::file, s).map_err(|e|format!("{}", e)).map(|pairs|pairs.filter(|pair|pair.as_rule() != Rule::EOI).map(Item::parse))
+}
+
+fn parse_number(s: &str) -> usize {
+    let parsed = match (s.strip_prefix("0x"), s.strip_prefix("0b")) {
+        (Some(hex), _) => usize::from_str_radix(hex, 16),
+        (_, Some(bin)) => usize::from_str_radix(bin, 2),
+        _ => s.parse(),
+    };
+    parsed.unwrap_or_else(|_|panic!("Number too large: {}", s))
 }
 
 trait Parse: Sized {
@@ -38,7 +47,7 @@ impl Parse for bool {
         match pair.as_str() {
             "0" => false,
             "1" => true,
-            p =>  unreachable!(p),
+            p =>  unreachable!("{}", p),
         }
     }
 }
@@ -64,6 +73,8 @@ impl Parse for StateAst {
     fn parse(pair: Pair) -> Self {
         match pair.as_rule() {
             Rule::bool => StateAst::Const(bool::parse(pair)),
+            Rule::number => StateAst::Num(parse_number(pair.as_str())),
+            Rule::string => StateAst::Str(pair.as_str()[1..pair.as_str().len() - 1].to_owned()),
             Rule::ident => StateAst::Ident(String::parse(pair)),
             Rule::state_not => StateAst::Not(<Box<StateAst>>::parse(pair.into_inner().next().unwrap())),
             r => unreachable!("{:?}", r),
@@ -83,6 +94,17 @@ impl Parse for Ast {
                 let args = pairs.map(Ast::parse).collect();
                 Ast::Call(ident, state, args)
             },
+            Rule::ast_or | Rule::ast_xor | Rule::ast_and => {
+                let op = match pair.as_rule() {
+                    Rule::ast_or => Op::Or,
+                    Rule::ast_xor => Op::Xor,
+                    _ => Op::And,
+                };
+                let mut pairs = pair.into_inner().map(Ast::parse);
+                let first = pairs.next().unwrap();
+                pairs.fold(first, |a, b|Ast::Binary(op, Box::new(a), Box::new(b)))
+            },
+            Rule::ast_not => Ast::Not(<Box<Ast>>::parse(pair.into_inner().next().unwrap())),
             r => unreachable!("{:?}", r),
         }
     }
@@ -107,7 +129,7 @@ impl Parse for Stmt {
                 let ast = pairs.next().map(Ast::parse).unwrap();
                 Stmt::Set(pattern, ast)
             },
-            Rule::bool | Rule::ast_call | Rule::ident => {
+            Rule::ast_or => {
                 Stmt::Call(Ast::parse(pair))
             },
             r => unreachable!("{:?}", r),
@@ -115,8 +137,82 @@ impl Parse for Stmt {
     }
 }
 
+/// The most inputs a truth table may have, as its table holds a row for every value of them.
+const MAX_TABLE_INPUTS: usize = 16;
+
+/// Reads the rows of a truth table into one word per value of the inputs, with the first input as the
+/// least significant bit of the value and the first output as the least significant bit of the word.
+/// A `-` in the inputs matches both values, and rows left out give 0.
+fn table_words(name: &str, inputs: usize, outputs: usize, rows: Vec<(String, String)>) -> Vec<StateAst> {
+    if inputs > MAX_TABLE_INPUTS {
+        panic!("{} has {} inputs, but tables may have at most {}", name, inputs, MAX_TABLE_INPUTS)
+    }
+    let mut words: Vec<Option<usize>> = vec![None; 1 << inputs];
+    for (key, value) in rows {
+        if key.len() != inputs || value.len() != outputs || value.contains('-') {
+            panic!("{} expects rows of {} input bits and {} output bits, but recieved {}: {}", name, inputs, outputs, key, value)
+        }
+        let word = value.chars().rev().fold(0, |acc, c|acc << 1 | (c == '1') as usize);
+        let fixed = key.chars().rev().fold(0, |acc, c|acc << 1 | (c == '1') as usize);
+        let free = key.chars().rev().fold(0, |acc, c|acc << 1 | (c == '-') as usize);
+        for (index, slot) in words.iter_mut().enumerate().filter(|(i, _)|i & !free == fixed) {
+            match slot {
+                Some(old) if *old != word => {
+                    let bits: String = (0..inputs).map(|i|if index >> i & 1 == 1 { '1' } else { '0' }).collect();
+                    panic!("{} gives two outputs for the inputs {}", name, bits)
+                },
+                _ => *slot = Some(word),
+            }
+        }
+    }
+    words.into_iter().map(|w|StateAst::Num(w.unwrap_or(0))).collect()
+}
+
+impl Parse for Item {
+    fn parse(pair: Pair) -> Self {
+        if pair.as_rule() != Rule::import {
+            let (name, func) = <(String, Func)>::parse(pair);
+            return Item::Func(name, func);
+        }
+        let target = pair.into_inner().nth(1).unwrap();
+        match target.as_rule() {
+            Rule::string => Item::Import(target.as_str()[1..target.as_str().len() - 1].to_owned(), None),
+            // `import a::b::f` imports `f` from `a/b.cir`, unless `a` is `std`.
+            _ => {
+                let mut path = <Vec<String>>::parse(target);
+                let func = path.pop().unwrap();
+                match &path[..] {
+                    [std, module] if std == "std" => Item::ImportStd(module.clone(), func),
+                    _ => Item::Import(format!("{}.cir", path.join("/")), Some(func)),
+                }
+            },
+        }
+    }
+}
+
+impl Parse for (String, String) {
+    fn parse(pair: Pair) -> Self {
+        debug_assert_eq!(pair.as_rule(), Rule::table_row);
+        let mut pairs = pair.into_inner();
+        let key = pairs.next().unwrap().as_str().to_owned();
+        let value = pairs.next().unwrap().as_str().to_owned();
+        (key, value)
+    }
+}
+
 impl Parse for (String, Func) {
     fn parse(pair: Pair) -> Self {
+        if pair.as_rule() == Rule::table {
+            // A truth table is a function whose body looks its outputs up in a ROM.
+            let mut pairs = pair.into_inner().skip(1);
+            let name = pairs.next().map(String::parse).unwrap();
+            let input = pairs.next().map(<Vec<String>>::parse).unwrap();
+            let output = pairs.next().map(<Vec<String>>::parse).unwrap();
+            let words = table_words(&name, input.len(), output.len(), pairs.map(<(String, String)>::parse).collect());
+            let rom = Ast::Call("rom".to_owned(), Some(words), input.iter().cloned().map(Ast::Wire).collect());
+            let stmts = vec![Stmt::Set(output.iter().cloned().map(Some).collect(), rom)];
+            return (name, Func { state: vec![], input, output, stmts });
+        }
         let mut pairs = pair.into_inner();
         let name = pairs.next().map(String::parse).unwrap();
         let func = Func {
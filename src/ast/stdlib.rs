@@ -0,0 +1,226 @@
+/// The modules of the standard library, embedded in the simulator, by name.
+/// `import std::adders::full_adder;` imports `full_adder` from the `adders` module.
+const MODULES: &[(&str, &str)] = &[
+    ("gates", include_str!("std/gates.cir")),
+    ("latches", include_str!("std/latches.cir")),
+    ("flipflops", include_str!("std/flipflops.cir")),
+    ("registers", include_str!("std/registers.cir")),
+    ("adders", include_str!("std/adders.cir")),
+    ("counters", include_str!("std/counters.cir")),
+    ("shift", include_str!("std/shift.cir")),
+    ("compare", include_str!("std/compare.cir")),
+    ("mux", include_str!("std/mux.cir")),
+    ("decoders", include_str!("std/decoders.cir")),
+];
+
+/// The source of the standard library module called `name`, if there is one.
+pub fn module(name: &str) -> Option<&'static str> {
+    MODULES.iter().find(|(n, _)|*n == name).map(|(_, src)|*src)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MODULES;
+    use crate::ast::parse;
+    use circuit_sim::base::WholeNewState;
+    use circuit_sim::circuit::Circuit;
+    use std::path::Path;
+
+    /// Updates to run after setting the inputs, enough for the deepest component to settle.
+    const SETTLE: usize = 64;
+
+    struct Sim {
+        circuit: Circuit,
+        state: WholeNewState,
+    }
+
+    impl Sim {
+        fn new(module: &str, func: &str) -> Self {
+            let src = format!("import std::{}::{};", module, func);
+            let program = parse(&src, Path::new("test.cir")).unwrap();
+            let sign = &program.env[func];
+            let mut circuit = program.funcs[sign.id].build_circuit(&program.funcs, sign);
+            let state = circuit.new_state();
+            Sim { circuit, state }
+        }
+        /// Sets the inputs, first input first, and returns the outputs once they have settled.
+        fn step(&mut self, inputs: &str) -> String {
+            self.circuit.set_input(inputs.chars().map(|c|c == '1').collect()).unwrap();
+            for _ in 0..SETTLE {
+                self.circuit.update(&mut self.state);
+            }
+            self.circuit.get_output(&self.state).into_iter().map(|b|if b { '1' } else { '0' }).collect()
+        }
+        /// Sets the inputs with the clock, the last input, low, then raises and lowers the clock,
+        /// returning the outputs after the rising edge.
+        fn pulse(&mut self, inputs: &str) -> String {
+            self.step(&format!("{}0", inputs));
+            let outputs = self.step(&format!("{}1", inputs));
+            self.step(&format!("{}0", inputs));
+            outputs
+        }
+    }
+
+    /// The `width` low bits of `value`, least significant first.
+    fn bits(value: u64, width: usize) -> String {
+        (0..width).map(|i|if value >> i & 1 == 1 { '1' } else { '0' }).collect()
+    }
+
+    /// Checks a combinational function against `expected` for every value of its inputs, first input as the least significant bit.
+    fn exhaustive(module: &str, func: &str, expected: impl Fn(u64) -> u64) {
+        sampled(module, func, 1, expected)
+    }
+
+    /// Checks a combinational function against `expected` for every `stride`th value of its inputs, for those with too many to try.
+    fn sampled(module: &str, func: &str, stride: usize, expected: impl Fn(u64) -> u64) {
+        let mut sim = Sim::new(module, func);
+        let (inputs, outputs) = (sim.circuit.input_count(), sim.circuit.output_count());
+        for value in (0..1 << inputs).step_by(stride) {
+            assert_eq!(sim.step(&bits(value, inputs)), bits(expected(value), outputs), "{} with inputs {}", func, bits(value, inputs));
+        }
+    }
+
+    /// The bit of `value` at `i`.
+    fn bit(value: u64, i: usize) -> u64 {
+        value >> i & 1
+    }
+
+    #[test]
+    fn modules_parse() {
+        for (name, src) in MODULES {
+            parse(src, Path::new(name)).unwrap();
+        }
+    }
+
+    #[test]
+    fn gates() {
+        exhaustive("gates", "xor3", |v|v.count_ones() as u64 & 1);
+        exhaustive("gates", "xnor3", |v|!v.count_ones() as u64 & 1);
+        exhaustive("gates", "imply", |v|(bit(v, 0) == 0 || bit(v, 1) == 1) as u64);
+        exhaustive("gates", "majority", |v|(v.count_ones() >= 2) as u64);
+        exhaustive("gates", "parity8", |v|v.count_ones() as u64 & 1);
+    }
+
+    #[test]
+    fn latches() {
+        let mut sim = Sim::new("latches", "sr_latch");
+        assert_eq!(sim.step("00"), "01");
+        assert_eq!(sim.step("10"), "10");
+        assert_eq!(sim.step("00"), "10");
+        assert_eq!(sim.step("01"), "01");
+        assert_eq!(sim.step("00"), "01");
+        let mut sim = Sim::new("latches", "d_latch");
+        assert_eq!(sim.step("10"), "01");
+        assert_eq!(sim.step("11"), "10");
+        assert_eq!(sim.step("01"), "01");
+        assert_eq!(sim.step("00"), "01");
+        assert_eq!(sim.step("10"), "01");
+    }
+
+    #[test]
+    fn flipflops() {
+        let mut sim = Sim::new("flipflops", "dff");
+        assert_eq!(sim.step("10"), "01");
+        assert_eq!(sim.step("11"), "10");
+        assert_eq!(sim.step("01"), "10");
+        assert_eq!(sim.step("00"), "10");
+        assert_eq!(sim.pulse("0"), "01");
+        let mut sim = Sim::new("flipflops", "dffe");
+        assert_eq!(sim.pulse("10"), "01");
+        assert_eq!(sim.pulse("11"), "10");
+        assert_eq!(sim.pulse("00"), "10");
+        assert_eq!(sim.pulse("01"), "01");
+        let mut sim = Sim::new("flipflops", "dffr");
+        assert_eq!(sim.pulse("10"), "10");
+        assert_eq!(sim.pulse("11"), "01");
+        let mut sim = Sim::new("flipflops", "tff");
+        assert_eq!(sim.pulse("0"), "01");
+        assert_eq!(sim.pulse("1"), "10");
+        assert_eq!(sim.pulse("1"), "01");
+        assert_eq!(sim.pulse("0"), "01");
+        let mut sim = Sim::new("flipflops", "jkff");
+        assert_eq!(sim.pulse("10"), "10");
+        assert_eq!(sim.pulse("00"), "10");
+        assert_eq!(sim.pulse("01"), "01");
+        assert_eq!(sim.pulse("11"), "10");
+        assert_eq!(sim.pulse("11"), "01");
+    }
+
+    #[test]
+    fn registers() {
+        let mut sim = Sim::new("registers", "register8");
+        assert_eq!(sim.pulse(&format!("{}1", bits(0xa5, 8))), bits(0xa5, 8));
+        assert_eq!(sim.pulse(&format!("{}0", bits(0x3c, 8))), bits(0xa5, 8));
+        assert_eq!(sim.pulse(&format!("{}1", bits(0x3c, 8))), bits(0x3c, 8));
+    }
+
+    #[test]
+    fn adders() {
+        exhaustive("adders", "half_adder", |v|bit(v, 0) + bit(v, 1));
+        exhaustive("adders", "full_adder", |v|bit(v, 0) + bit(v, 1) + bit(v, 2));
+        exhaustive("adders", "adder4", |v|(v & 0xf) + (v >> 4 & 0xf) + (v >> 8));
+        sampled("adders", "adder8", 61, |v|(v & 0xff) + (v >> 8 & 0xff) + (v >> 16));
+    }
+
+    #[test]
+    fn counters() {
+        let mut sim = Sim::new("counters", "counter4");
+        for n in 1..20 {
+            assert_eq!(sim.pulse("10"), bits(n % 16, 4));
+        }
+        assert_eq!(sim.pulse("00"), bits(3, 4));
+        assert_eq!(sim.pulse("11"), bits(0, 4));
+        let mut sim = Sim::new("counters", "counter8");
+        for n in 1..300 {
+            assert_eq!(sim.pulse("10"), bits(n % 256, 8));
+        }
+    }
+
+    #[test]
+    fn shift() {
+        let mut sim = Sim::new("shift", "shift4");
+        assert_eq!(sim.pulse("1"), "1000");
+        assert_eq!(sim.pulse("0"), "0100");
+        assert_eq!(sim.pulse("1"), "1010");
+        assert_eq!(sim.pulse("1"), "1101");
+        let mut sim = Sim::new("shift", "shift8");
+        for n in 0..8 {
+            sim.pulse(if n % 3 == 0 { "1" } else { "0" });
+        }
+        assert_eq!(sim.step("00"), "01001001");
+        let mut sim = Sim::new("shift", "shift_load4");
+        assert_eq!(sim.pulse("011010"), "1010");
+        assert_eq!(sim.pulse("000000"), "0101");
+        assert_eq!(sim.pulse("100000"), "1010");
+    }
+
+    #[test]
+    fn compare() {
+        let compare = |v: u64, width: usize| {
+            let (a, b) = (v & ((1 << width) - 1), v >> width);
+            (a < b) as u64 | ((a == b) as u64) << 1 | ((a > b) as u64) << 2
+        };
+        exhaustive("compare", "compare1", |v|compare(v, 1));
+        exhaustive("compare", "eq4", |v|(v & 0xf == v >> 4) as u64);
+        exhaustive("compare", "compare4", |v|compare(v, 4));
+        sampled("compare", "compare8", 29, |v|compare(v, 8));
+    }
+
+    #[test]
+    fn mux() {
+        exhaustive("mux", "mux2", |v|bit(v, bit(v, 2) as usize));
+        exhaustive("mux", "mux4", |v|bit(v, (v >> 4) as usize));
+        exhaustive("mux", "mux8", |v|bit(v, (v >> 8) as usize));
+        exhaustive("mux", "demux2", |v|bit(v, 0) << bit(v, 1));
+        exhaustive("mux", "demux4", |v|bit(v, 0) << (v >> 1));
+    }
+
+    #[test]
+    fn decoders() {
+        exhaustive("decoders", "decoder2", |v|bit(v, 2) << (v & 3));
+        exhaustive("decoders", "decoder3", |v|bit(v, 3) << (v & 7));
+        exhaustive("decoders", "priority4", |v|if v == 0 { 0 } else { (63 - v.leading_zeros() as u64) | 4 });
+        let digits = [0x3f, 0x06, 0x5b, 0x4f, 0x66, 0x6d, 0x7d, 0x07, 0x7f, 0x6f, 0x77, 0x7c, 0x39, 0x5e, 0x79, 0x71];
+        exhaustive("decoders", "seven_segment", |v|digits[v as usize]);
+    }
+}
@@ -1,63 +1,206 @@
 use rand::Rng;
 use std::path::Path;
 use std::fs::{ read_to_string, write };
+use std::io::{ Error, ErrorKind };
 
 pub type Data = bool;
+/// How a bus resolves its drivers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BusMode {
+  /// Floating and conflicting buses read a random value.
+  Float,
+  /// A floating bus reads 1.
+  PullUp,
+  /// A floating bus reads 0.
+  PullDown,
+  /// Wired-AND: the bus reads 0 while any driver pulls it low and 1 otherwise, ignoring high drivers.
+  OpenDrain,
+}
+impl BusMode {
+  pub const ALL: [BusMode; 4] = [BusMode::Float, BusMode::PullUp, BusMode::PullDown, BusMode::OpenDrain];
+  pub fn name(self) -> &'static str {
+    match self {
+      BusMode::Float => "float",
+      BusMode::PullUp => "pullup",
+      BusMode::PullDown => "pulldown",
+      BusMode::OpenDrain => "open_drain",
+    }
+  }
+  pub fn parse(name: &str) -> Option<BusMode> {
+    BusMode::ALL.iter().copied().find(|mode|mode.name() == name)
+  }
+  /// The value the bus reads when nothing drives it, if it is pulled.
+  pub fn pull(self) -> Option<Data> {
+    match self {
+      BusMode::Float => None,
+      BusMode::PullUp | BusMode::OpenDrain => Some(true),
+      BusMode::PullDown => Some(false),
+    }
+  }
+}
 #[derive(Debug)]
 pub enum Component {
   Source(Data),
   Buffer(usize),
   Inverter(usize),
-  Or(usize, usize),
-  And(usize, usize),
-  Nor(usize, usize),
-  Nand(usize, usize),
-  Bus(Vec<(usize, usize)>),
+  Or(Box<[usize]>),
+  And(Box<[usize]>),
+  Nor(Box<[usize]>),
+  Nand(Box<[usize]>),
+  Xor(usize, usize),
+  Xnor(usize, usize),
+  /// The mode and the high and low signals of every driver.
+  Bus(BusMode, Vec<(usize, usize)>),
+  Clock { period: usize, high: usize, phase: usize },
+  /// Data inputs and select inputs, least significant first. Selecting past the data gives 0.
+  Mux(Box<[usize]>, Box<[usize]>),
+  /// One output of a decoder: high when the select inputs, least significant first, encode the value.
+  Decoder(Box<[usize]>, usize),
+  /// One bit of the index of the highest set input, or 0 when none are set.
+  Encoder(Box<[usize]>, usize),
+  /// A lookup table, such as one data bit of a ROM: the inputs, least significant first, and the output
+  /// for every value they encode. Values past the end of the table give 0.
+  Lut(Box<[usize]>, Box<[Data]>),
+  /// One data bit of a RAM: the index of its memory, the address inputs and the bit.
+  /// The write ports belong to the memory.
+  Ram(usize, Box<[usize]>, usize),
+}
+/// The contents and write ports of a RAM, with every wire list least significant first.
+#[derive(Clone, Debug)]
+pub struct Memory {
+  pub address: Box<[usize]>,
+  pub data: Box<[usize]>,
+  pub write: usize,
+  /// Writes happen on the rising edge of the clock, or whenever `write` is high without one.
+  pub clock: Option<usize>,
+  /// The first words of the initial contents, with the rest starting at 0.
+  pub init: Box<[u64]>,
+}
+impl Memory {
+  /// The widest address a memory may have, keeping its contents to a few megabytes.
+  pub const MAX_ADDRESS: usize = 20;
+  /// Checks the widths of the ports and the initial contents.
+  pub fn check(&self) -> Result<(), String> {
+    if self.address.len() > Memory::MAX_ADDRESS {
+      return Err(format!("A RAM has {} address bits, but at most {} are supported", self.address.len(), Memory::MAX_ADDRESS));
+    }
+    if self.data.is_empty() || self.data.len() > 64 {
+      return Err(format!("A RAM has {} data bits, but needs 1 to 64", self.data.len()));
+    }
+    if self.init.len() > self.size() {
+      return Err(format!("A RAM holds {} words, but was given {}", self.size(), self.init.len()));
+    }
+    Ok(())
+  }
+  pub fn size(&self) -> usize {
+    1 << self.address.len()
+  }
+  pub fn inputs(&self) -> Vec<usize> {
+    self.address.iter().chain(self.data.iter()).chain(Some(&self.write)).chain(self.clock.as_ref()).copied().collect()
+  }
+  fn write(&self, words: &mut [u64], wires: &[Data], old_wires: &[Data]) {
+    let edge = self.clock.is_none_or(|clock|wires[clock] && !old_wires[clock]);
+    if wires[self.write] && edge {
+      words[index(wires, &self.address)] = self.data.iter().rev().fold(0, |acc, d|acc << 1 | wires[*d] as u64);
+    }
+  }
+}
+/// The number the wires encode, least significant first.
+fn index(wires: &[Data], select: &[usize]) -> usize {
+  select.iter().rev().fold(0, |acc, s|acc << 1 | wires[*s] as usize)
 }
 impl Component {
-  fn update(&self, wires: &[Data]) -> Data {
+  pub fn inputs(&self) -> Vec<usize> {
+    match *self {
+      Component::Source(_) | Component::Clock { .. } => vec![],
+      Component::Buffer(in0) | Component::Inverter(in0) => vec![in0],
+      Component::Or(ref inputs) | Component::And(ref inputs) | Component::Nor(ref inputs) | Component::Nand(ref inputs) => inputs.to_vec(),
+      Component::Xor(in0, in1) | Component::Xnor(in0, in1) => vec![in0, in1],
+      Component::Mux(ref data, ref select) => data.iter().chain(select.iter()).copied().collect(),
+      Component::Decoder(ref inputs, _) | Component::Encoder(ref inputs, _) | Component::Lut(ref inputs, _) | Component::Ram(_, ref inputs, _) => inputs.to_vec(),
+      Component::Bus(_, ref inputs) => inputs.iter().flat_map(|(high, low)|vec![*high, *low]).collect(),
+    }
+  }
+  /// Whether a clock is high after `step` updates.
+  pub fn clock_value(period: usize, high: usize, phase: usize, step: usize) -> Data {
+    (step + phase) % period < high
+  }
+  fn update(&self, wires: &[Data], memories: &[Box<[u64]>], step: usize) -> Data {
     match *self {
       Component::Source(out) => out,
       Component::Buffer(in0) => wires[in0],
       Component::Inverter(in0) => !wires[in0],
-      Component::Or(in0, in1) => wires[in0] || wires[in1],
-      Component::And(in0, in1) => wires[in0] && wires[in1],
-      Component::Nor(in0, in1) => !(wires[in0] || wires[in1]),
-      Component::Nand(in0, in1) => !(wires[in0] && wires[in1]),
-      Component::Bus(ref inputs) => {
+      Component::Or(ref inputs) => inputs.iter().any(|i|wires[*i]),
+      Component::And(ref inputs) => inputs.iter().all(|i|wires[*i]),
+      Component::Nor(ref inputs) => !inputs.iter().any(|i|wires[*i]),
+      Component::Nand(ref inputs) => !inputs.iter().all(|i|wires[*i]),
+      Component::Xor(in0, in1) => wires[in0] != wires[in1],
+      Component::Xnor(in0, in1) => wires[in0] == wires[in1],
+      Component::Bus(mode, ref inputs) => {
         let mut up: bool = false;
         let mut down: bool = false;
         for (s_up, s_down) in inputs.iter() {
           up |= wires[*s_up];
           down |= wires[*s_down];
         }
-        match (up, down) {
-          (false, true) => false,
-          (true, false) => true,
+        match (mode, up, down) {
+          (BusMode::OpenDrain, _, down) => !down,
+          (_, false, true) => false,
+          (_, true, false) => true,
+          (mode, false, false) if mode.pull().is_some() => mode.pull().unwrap(),
           _ => rand::thread_rng().gen(),
         }
       },
+      Component::Clock { period, high, phase } => Component::clock_value(period, high, phase, step),
+      Component::Mux(ref data, ref select) => data.get(index(wires, select)).is_some_and(|d|wires[*d]),
+      Component::Decoder(ref select, value) => index(wires, select) == value,
+      Component::Encoder(ref inputs, bit) => inputs.iter().rposition(|i|wires[*i]).is_some_and(|i|i >> bit & 1 == 1),
+      Component::Lut(ref inputs, ref table) => table.get(index(wires, inputs)).copied().unwrap_or(false),
+      Component::Ram(memory, ref address, bit) => memories[memory][index(wires, address)] >> bit & 1 == 1,
     }
   }
 }
+/// Checks that every memory is valid and every RAM bit reads a memory it fits.
+pub(crate) fn check_memories(components: &[(Component, Data)], memories: &[Memory]) -> Result<(), String> {
+  memories.iter().try_for_each(Memory::check)?;
+  let invalid = |m: usize, address: &[usize], bit: usize|memories.get(m).is_none_or(|m|bit >= m.data.len() || address.len() != m.address.len());
+  if components.iter().any(|(c, _)|matches!(c, Component::Ram(m, address, bit) if invalid(*m, address, *bit))) {
+    return Err("A RAM bit does not match its memory".to_owned());
+  }
+  Ok(())
+}
 #[derive(Default, Debug)]
 pub struct WholeNew {
   pub components: Box<[(Component, Data)]>,
+  pub memories: Box<[Memory]>,
 }
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct WholeNewState {
   pub components: Box<[Data]>,
   old_components: Box<[Data]>,
+  /// The words of every memory.
+  pub memories: Box<[Box<[u64]>]>,
+  pub steps: usize,
 }
 impl WholeNew {
   pub fn new_state(&self) -> WholeNewState {
     let components = self.components.iter().map(|(_,p)|p).cloned().collect::<Vec<_>>().into_boxed_slice();
-    WholeNewState { old_components: components.clone(), components }
+    let memories = self.memories.iter().map(|m|{
+      let mut words = vec![0; m.size()];
+      words[..m.init.len()].copy_from_slice(&m.init);
+      words.into_boxed_slice()
+    }).collect();
+    WholeNewState { old_components: components.clone(), components, memories, steps: 0 }
   }
   pub fn update(&self, state: &mut WholeNewState) {
+    // Writes see the same wires as the components, and the wires before them for clock edges.
+    for (memory, words) in self.memories.iter().zip(state.memories.iter_mut()) {
+      memory.write(words, &state.components, &state.old_components);
+    }
     std::mem::swap(&mut state.components, &mut state.old_components);
+    state.steps += 1;
     for (comp, out) in self.components.iter().map(|(comp,_)|comp).zip(state.components.iter_mut()) {
-      *out = comp.update(&state.old_components);
+      *out = comp.update(&state.old_components, &state.memories, state.steps);
     }
   }
 }
@@ -67,11 +210,22 @@ impl WholeNewState {
   }
   pub fn load<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {
     let file = read_to_string(path)?;
+    let file = file.trim_end();
     if file.len() != self.components.len() {
-      panic!("The file contains a {} bit state, but this circuit uses a {} bit state.", file.len(), self.components.len());
+      return Err(Error::new(ErrorKind::InvalidData, format!("The file contains a {} bit state, but this circuit uses a {} bit state.", file.len(), self.components.len())));
     }
-    self.components.iter_mut().zip(file.chars().map(|c|match c { '1' => true, '0' => false, c => panic!("'{}' is not a valid bit value.", c) })).for_each(|(wire, val)|*wire = val);
+    let values = file.chars().map(|c|match c {
+      '1' => Ok(true),
+      '0' => Ok(false),
+      c => Err(Error::new(ErrorKind::InvalidData, format!("'{}' is not a valid bit value.", c))),
+    }).collect::<Result<Vec<_>, _>>()?;
+    self.components.copy_from_slice(&values);
     self.old_components.copy_from_slice(&self.components);
     Ok(())
   }
+  /// Sets a wire as if it had held the value since the last update.
+  pub fn set(&mut self, wire: usize, value: Data) {
+    self.components[wire] = value;
+    self.old_components[wire] = value;
+  }
 }
@@ -0,0 +1,422 @@
+use std::collections::{ HashMap, HashSet };
+use std::io::{ self, Write };
+use crate::base::{ BusMode, Component };
+use crate::circuit::{ Builder, Circuit };
+
+impl Circuit {
+  fn blif_net(&self, wire: usize) -> String {
+    match self.port_name(wire) {
+      Some(name) if self.inputs().contains(&wire) || self.outputs().contains(&wire) => name.to_owned(),
+      _ => format!("n{}", wire),
+    }
+  }
+  /// Writes the flattened circuit as a BLIF model.
+  /// With `delay` every gate is followed by a latch holding its initial value, which keeps the one tick gate delay of the simulation.
+  pub fn write_blif<W: Write>(&self, mut out: W, delay: bool) -> io::Result<()> {
+    if !self.memories().is_empty() {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "BLIF can't describe RAM"));
+    }
+    writeln!(out, ".model {}", self.name())?;
+    write!(out, ".inputs")?;
+    for input in self.inputs() {
+      write!(out, " {}", self.blif_net(*input))?;
+    }
+    // BLIF has no clock generators, so they are left for the environment to drive.
+    for (id, (component, _)) in self.components().iter().enumerate() {
+      if let Component::Clock { .. } = component {
+        write!(out, " {}", self.blif_net(id))?;
+      }
+    }
+    writeln!(out)?;
+    write!(out, ".outputs")?;
+    for output in self.outputs() {
+      write!(out, " {}", self.blif_net(*output))?;
+    }
+    writeln!(out)?;
+    for (id, (component, default)) in self.components().iter().enumerate() {
+      if self.inputs().contains(&id) || matches!(component, Component::Clock { .. }) {
+        continue;
+      }
+      let net = self.blif_net(id);
+      let target = if delay { format!("{}$next", net) } else { net.clone() };
+      write!(out, ".names")?;
+      for input in component.inputs() {
+        write!(out, " {}", self.blif_net(input))?;
+      }
+      writeln!(out, " {}", target)?;
+      match component {
+        Component::Source(true) => writeln!(out, "1")?,
+        Component::Source(false) => {},
+        Component::Buffer(_) => writeln!(out, "1 1")?,
+        Component::Inverter(_) => writeln!(out, "0 1")?,
+        Component::Or(inputs) => write_rows(&mut out, inputs.len(), '1')?,
+        Component::And(inputs) => writeln!(out, "{} 1", "1".repeat(inputs.len()))?,
+        Component::Nor(inputs) => writeln!(out, "{} 1", "0".repeat(inputs.len()))?,
+        Component::Nand(inputs) => write_rows(&mut out, inputs.len(), '0')?,
+        Component::Xor(_, _) => writeln!(out, "10 1\n01 1")?,
+        Component::Xnor(_, _) => writeln!(out, "00 1\n11 1")?,
+        Component::Mux(data, select) => for i in 0..data.len().min(1 << select.len()) {
+          let data: String = (0..data.len()).map(|j|if i == j { '1' } else { '-' }).collect();
+          writeln!(out, "{}{} 1", data, bits(i, select.len()))?;
+        },
+        Component::Decoder(select, value) => if *value >> select.len() == 0 {
+          writeln!(out, "{} 1", bits(*value, select.len()))?;
+        },
+        Component::Encoder(inputs, bit) => for i in (0..inputs.len()).filter(|i|i >> bit & 1 == 1) {
+          let row: String = (0..inputs.len()).map(|j|if i == j { '1' } else if j > i { '0' } else { '-' }).collect();
+          writeln!(out, "{} 1", row)?;
+        },
+        Component::Lut(inputs, table) => for i in (0..table.len()).filter(|i|table[*i]) {
+          writeln!(out, "{} 1", bits(i, inputs.len()))?;
+        },
+        Component::Bus(BusMode::PullUp | BusMode::OpenDrain, inputs) => {
+          // High unless pulled low; a pulled up bus with conflicting drivers reads as low.
+          writeln!(out, "{} 1", "-0".repeat(inputs.len()))?;
+        },
+        Component::Bus(_, inputs) => {
+          // Driven high by any input and low by none; conflicts and floating buses read as low.
+          for i in 0..inputs.len() {
+            let row: String = (0..inputs.len()).flat_map(|j|vec![if i == j { '1' } else { '-' }, '0']).collect();
+            writeln!(out, "{} 1", row)?;
+          }
+        },
+        Component::Clock { .. } | Component::Ram(..) => unreachable!(),
+      }
+      if delay {
+        writeln!(out, ".latch {} {} {}", target, net, *default as u8)?;
+      }
+    }
+    writeln!(out, ".end")
+  }
+  /// Reads the first model of a BLIF file.
+  /// Latches become gate level flip-flops, and latches without a control signal are clocked by an added `clk` input,
+  /// or `clk_1`, `clk_2` and so on if the model already has a net by that name.
+  pub fn read_blif(src: &str) -> Result<Circuit, String> {
+    let words: HashSet<&str> = src.split_whitespace().collect();
+    let clock_name = (0..).map(|i|if i == 0 { "clk".to_owned() } else { format!("clk_{}", i) }).find(|name|!words.contains(name.as_str())).unwrap();
+    let mut reader = BlifReader { builder: Circuit::builder(), nets: HashMap::new(), defined: HashSet::new(), clock: None, clock_name };
+    let mut lines = logical_lines(src).into_iter().peekable();
+    let mut outputs = vec![];
+    while let Some((line_no, line)) = lines.next() {
+      let words: Vec<&str> = line.split_whitespace().collect();
+      let err = |msg: String|format!("line {}: {}", line_no, msg);
+      match words[0] {
+        ".model" => reader.builder.set_name(words.get(1).copied().unwrap_or("blif")),
+        ".inputs" => for name in &words[1..] {
+          let slot = reader.net(name);
+          reader.define(slot).map_err(err)?;
+          reader.builder.add_input(slot, false);
+        },
+        ".outputs" => outputs.extend(words[1..].iter().map(|s|s.to_string())),
+        ".names" => {
+          let (output, inputs) = words[1..].split_last().ok_or_else(||err("Expected an output".to_owned()))?;
+          let mut cover = vec![];
+          while let Some((_, row)) = lines.peek() {
+            if row.starts_with('.') {
+              break;
+            }
+            cover.push(lines.next().unwrap().1);
+          }
+          reader.names(inputs, output, &cover).map_err(err)?;
+        },
+        ".latch" => {
+          let (input, output, kind, control, init) = match words[1..] {
+            [input, output] => (input, output, "re", None, "3"),
+            [input, output, init] => (input, output, "re", None, init),
+            [input, output, kind, control] => (input, output, kind, Some(control), "3"),
+            [input, output, kind, control, init] => (input, output, kind, Some(control), init),
+            _ => return Err(err(format!("Expected 2 to 5 arguments to .latch, recieved {}", words.len() - 1))),
+          };
+          let control = control.filter(|c|*c != "NIL");
+          reader.latch(input, output, kind, control, init == "1").map_err(err)?;
+        },
+        ".end" => break,
+        ".clock" | ".default_input_arrival" | ".default_output_required" => {},
+        cmd => return Err(err(format!("Unsupported BLIF construct: {}", cmd))),
+      }
+    }
+    for output in outputs {
+      let slot = reader.net(&output);
+      reader.builder.add_output(slot);
+    }
+    if let Some((name, _)) = reader.nets.iter().find(|(_, slot)|!reader.defined.contains(slot)) {
+      return Err(format!("The net {} is never driven", name));
+    }
+    Ok(reader.builder.build())
+  }
+}
+
+/// The lowest `width` bits of `value`, least significant first.
+fn bits(value: usize, width: usize) -> String {
+  (0..width).map(|i|if value >> i & 1 == 1 { '1' } else { '0' }).collect()
+}
+
+/// Writes one cover row per input, matching when that input has `value`.
+fn write_rows<W: Write>(out: &mut W, inputs: usize, value: char) -> io::Result<()> {
+  for i in 0..inputs {
+    let row: String = (0..inputs).map(|j|if i == j { value } else { '-' }).collect();
+    writeln!(out, "{} 1", row)?;
+  }
+  Ok(())
+}
+
+fn logical_lines(src: &str) -> Vec<(usize, String)> {
+  let mut lines = vec![];
+  let mut current: Option<(usize, String)> = None;
+  for (i, line) in src.lines().enumerate() {
+    let line = line.split('#').next().unwrap().trim_end();
+    let (line, continued) = match line.strip_suffix('\\') {
+      Some(line) => (line, true),
+      None => (line, false),
+    };
+    let entry = current.get_or_insert_with(||(i + 1, String::new()));
+    entry.1.push(' ');
+    entry.1.push_str(line);
+    if !continued {
+      let (no, line) = current.take().unwrap();
+      if !line.trim().is_empty() {
+        lines.push((no, line.trim().to_owned()));
+      }
+    }
+  }
+  lines.extend(current.filter(|(_, l)|!l.trim().is_empty()));
+  lines
+}
+
+struct BlifReader {
+  builder: Builder,
+  nets: HashMap<String, usize>,
+  defined: HashSet<usize>,
+  clock: Option<usize>,
+  /// The name of the added clock, which no net in the model uses.
+  clock_name: String,
+}
+
+impl BlifReader {
+  fn net(&mut self, name: &str) -> usize {
+    if let Some(slot) = self.nets.get(name) {
+      return *slot;
+    }
+    let slot = self.builder.new_slot();
+    self.nets.insert(name.to_owned(), slot);
+    self.builder.name_wire(slot, name.to_owned());
+    slot
+  }
+  fn define(&mut self, slot: usize) -> Result<(), String> {
+    if !self.defined.insert(slot) {
+      return Err("A net is driven more than once".to_owned());
+    }
+    Ok(())
+  }
+  fn place(&mut self, slot: usize, component: Component, default: bool) -> Result<(), String> {
+    self.define(slot)?;
+    self.builder.place_component(slot, component, default);
+    Ok(())
+  }
+  fn gate(&mut self, component: Component, default: bool) -> usize {
+    self.builder.add_component(component, default)
+  }
+  fn reduce(&mut self, wires: Vec<usize>, gate: fn(Box<[usize]>) -> Component, target: usize) -> Result<(), String> {
+    match wires[..] {
+      [a] => self.place(target, Component::Buffer(a), false),
+      _ => self.place(target, gate(wires.into_boxed_slice()), false),
+    }
+  }
+  fn names(&mut self, inputs: &[&str], output: &str, cover: &[String]) -> Result<(), String> {
+    let target = self.net(output);
+    let inputs: Vec<usize> = inputs.iter().map(|i|self.net(i)).collect();
+    let mut on_set = true;
+    let mut terms = vec![];
+    for row in cover {
+      let words: Vec<&str> = row.split_whitespace().collect();
+      let (plane, value) = match words[..] {
+        [value] if inputs.is_empty() => ("", value),
+        [plane, value] if plane.len() == inputs.len() => (plane, value),
+        _ => return Err(format!("Malformed cover row: {}", row)),
+      };
+      on_set = match value {
+        "1" => true,
+        "0" => false,
+        v => return Err(format!("Invalid output value: {}", v)),
+      };
+      let mut literals = vec![];
+      for (c, input) in plane.chars().zip(&inputs) {
+        match c {
+          '1' => literals.push(*input),
+          '0' => literals.push(self.gate(Component::Inverter(*input), true)),
+          '-' => {},
+          c => return Err(format!("Invalid cover character: {}", c)),
+        }
+      }
+      terms.push(literals);
+    }
+    if terms.is_empty() {
+      return self.place(target, Component::Source(false), false);
+    }
+    if terms.iter().any(Vec::is_empty) {
+      return self.place(target, Component::Source(on_set), on_set);
+    }
+    let mut products = vec![];
+    for literals in terms {
+      products.push(match literals[..] {
+        [wire] => wire,
+        _ => {
+          let slot = self.builder.new_slot();
+          self.reduce(literals, Component::And, slot)?;
+          slot
+        },
+      });
+    }
+    if on_set {
+      self.reduce(products, Component::Or, target)
+    } else {
+      let sum = self.builder.new_slot();
+      self.reduce(products, Component::Or, sum)?;
+      self.place(target, Component::Inverter(sum), true)
+    }
+  }
+  fn latch(&mut self, input: &str, output: &str, kind: &str, control: Option<&str>, init: bool) -> Result<(), String> {
+    let d = self.net(input);
+    let q = self.net(output);
+    let clock = match control {
+      Some(control) => self.net(control),
+      None => match self.clock {
+        Some(clock) => clock,
+        None => {
+          let clock = self.net(&self.clock_name.clone());
+          self.define(clock)?;
+          self.builder.add_input(clock, false);
+          self.clock = Some(clock);
+          clock
+        },
+      },
+    };
+    let enable = match kind {
+      "re" => self.builder.add_rising_edge(clock),
+      "fe" => {
+        let clock = self.gate(Component::Inverter(clock), true);
+        self.builder.add_rising_edge(clock)
+      },
+      "ah" => clock,
+      "al" => self.gate(Component::Inverter(clock), true),
+      "as" => return Err("Asynchronous latches are not supported".to_owned()),
+      kind => return Err(format!("Unknown latch type: {}", kind)),
+    };
+    let (set, reset) = self.builder.add_d_latch_input(d, enable);
+    self.define(q)?;
+    self.builder.place_sr_latch(q, set, reset, init);
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::base::{ Component, Memory };
+  use crate::circuit::Circuit;
+
+  const FULL_ADDER: &str = "\
+.model full_adder
+.inputs a b c
+.outputs s co
+.names a b c s
+100 1
+010 1
+001 1
+111 1
+.names a b c co
+11- 1
+1-1 1
+-11 1
+.end
+";
+
+  /// The outputs once the circuit has settled with `inputs`.
+  fn settle(circuit: &mut Circuit, inputs: &[bool]) -> Vec<bool> {
+    circuit.set_input(inputs.to_vec()).unwrap();
+    let mut state = circuit.new_state();
+    for _ in 0..32 {
+      circuit.update(&mut state);
+    }
+    circuit.get_output(&state)
+  }
+
+  fn write(circuit: &Circuit, delay: bool) -> String {
+    let mut out = vec![];
+    circuit.write_blif(&mut out, delay).unwrap();
+    String::from_utf8(out).unwrap()
+  }
+
+  fn read_err(src: &str) -> String {
+    Circuit::read_blif(src).unwrap_err()
+  }
+
+  #[test]
+  fn round_trip() {
+    let mut circuit = Circuit::read_blif(FULL_ADDER).unwrap();
+    let mut read = Circuit::read_blif(&write(&circuit, false)).unwrap();
+    assert_eq!(read.name(), "full_adder");
+    assert_eq!((read.input_count(), read.output_count()), (3, 2));
+    for value in 0..8 {
+      let inputs: Vec<bool> = (0..3).map(|i|value >> i & 1 == 1).collect();
+      let sum = inputs.iter().filter(|b|**b).count();
+      assert_eq!(settle(&mut circuit, &inputs), [sum & 1 == 1, sum >= 2]);
+      assert_eq!(settle(&mut read, &inputs), [sum & 1 == 1, sum >= 2]);
+    }
+  }
+
+  #[test]
+  fn delay_adds_latches() {
+    let circuit = Circuit::read_blif(FULL_ADDER).unwrap();
+    let blif = write(&circuit, true);
+    assert!(blif.contains(".latch"));
+    let read = Circuit::read_blif(&blif).unwrap();
+    assert_eq!(read.find_port("clk"), Some((true, 3)));
+  }
+
+  #[test]
+  fn latch() {
+    let src = ".model l\n.inputs d\n.outputs q\n.latch d q 0\n.end\n";
+    let mut circuit = Circuit::read_blif(src).unwrap();
+    let mut state = circuit.new_state();
+    let mut step = |circuit: &mut Circuit, inputs: [bool; 2]| {
+      circuit.set_input(inputs.to_vec()).unwrap();
+      for _ in 0..32 {
+        circuit.update(&mut state);
+      }
+      circuit.get_output(&state)[0]
+    };
+    assert!(!step(&mut circuit, [true, false]));
+    assert!(step(&mut circuit, [true, true]));
+    assert!(step(&mut circuit, [false, false]));
+    assert!(!step(&mut circuit, [false, true]));
+  }
+
+  #[test]
+  fn clock_name_avoids_nets() {
+    let src = ".model l\n.inputs clk d\n.outputs q\n.latch d q 0\n.end\n";
+    let circuit = Circuit::read_blif(src).unwrap();
+    assert_eq!(circuit.find_port("clk_1"), Some((true, 2)));
+  }
+
+  #[test]
+  fn ram_is_rejected() {
+    let mut builder = Circuit::builder();
+    let wire = builder.add_component(Component::Source(false), false);
+    builder.add_memory(Memory { address: [wire].into(), data: [wire].into(), write: wire, clock: None, init: [].into() });
+    let err = builder.build().write_blif(vec![], false).unwrap_err();
+    assert_eq!(err.to_string(), "BLIF can't describe RAM");
+  }
+
+  #[test]
+  fn errors() {
+    assert_eq!(read_err(".model m\n.subckt f a=b\n"), "line 2: Unsupported BLIF construct: .subckt");
+    assert_eq!(read_err(".model m\n.outputs o\n.end\n"), "The net o is never driven");
+    assert_eq!(read_err(".inputs a\n.names a\n1\n.names a\n1\n"), "line 2: A net is driven more than once");
+    assert_eq!(read_err(".inputs a b\n.names a b o\n1 1\n"), "line 2: Malformed cover row: 1 1");
+    assert_eq!(read_err(".inputs a\n.names a o\n1 2\n"), "line 2: Invalid output value: 2");
+    assert_eq!(read_err(".inputs a\n.names a o\nx 1\n"), "line 2: Invalid cover character: x");
+    assert_eq!(read_err(".inputs d\n.latch d\n"), "line 2: Expected 2 to 5 arguments to .latch, recieved 1");
+    assert_eq!(read_err(".inputs d c\n.latch d q as c\n"), "line 2: Asynchronous latches are not supported");
+    assert_eq!(read_err(".inputs d c\n.latch d q xx c\n"), "line 2: Unknown latch type: xx");
+  }
+}
@@ -0,0 +1,329 @@
+//! A compact binary encoding of built circuits, used to skip parsing unchanged sources.
+//!
+//! All numbers are little endian `u32`s, except the `u64` source hash. The file starts with the
+//! magic bytes `CSIM`, the format version and the source hash, followed by the components,
+//! memories, inputs, outputs, instances and owners in the order they appear in `Circuit`.
+use std::convert::TryInto;
+use std::io::{ self, Write };
+use crate::base::{ check_memories, BusMode, Component, Memory, WholeNew };
+use crate::circuit::{ Circuit, Instance };
+
+const MAGIC: &[u8; 4] = b"CSIM";
+const VERSION: u32 = 5;
+
+/// 64 bit FNV-1a, which unlike the standard library hashers is stable between builds.
+pub fn source_hash(data: &[u8]) -> u64 {
+  data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b|(hash ^ *b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+struct Writer<W> {
+  out: W,
+}
+
+impl<W: Write> Writer<W> {
+  fn u32(&mut self, n: usize) -> io::Result<()> {
+    let n: u32 = n.try_into().map_err(|_|io::Error::new(io::ErrorKind::InvalidInput, "The circuit is too large"))?;
+    self.out.write_all(&n.to_le_bytes())
+  }
+  fn u64(&mut self, n: u64) -> io::Result<()> {
+    self.out.write_all(&n.to_le_bytes())
+  }
+  fn str(&mut self, s: &str) -> io::Result<()> {
+    self.u32(s.len())?;
+    self.out.write_all(s.as_bytes())
+  }
+  fn wires(&mut self, wires: &[usize]) -> io::Result<()> {
+    self.u32(wires.len())?;
+    wires.iter().try_for_each(|w|self.u32(*w))
+  }
+}
+
+struct Reader<'a> {
+  data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+  fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+    if self.data.len() < len {
+      return Err("Unexpected end of file".to_owned());
+    }
+    let (bytes, rest) = self.data.split_at(len);
+    self.data = rest;
+    Ok(bytes)
+  }
+  fn u8(&mut self) -> Result<u8, String> {
+    Ok(self.bytes(1)?[0])
+  }
+  fn u32(&mut self) -> Result<usize, String> {
+    Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()) as usize)
+  }
+  fn u64(&mut self) -> Result<u64, String> {
+    Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+  }
+  fn str(&mut self) -> Result<String, String> {
+    let len = self.u32()?;
+    String::from_utf8(self.bytes(len)?.to_vec()).map_err(|e|format!("{}", e))
+  }
+  fn wire(&mut self, len: usize) -> Result<usize, String> {
+    Some(self.u32()?).filter(|w|*w < len).ok_or_else(||"Invalid wire".to_owned())
+  }
+  fn wires(&mut self, len: usize) -> Result<Vec<usize>, String> {
+    let count = self.u32()?;
+    (0..count).map(|_|self.wire(len)).collect()
+  }
+}
+
+impl Circuit {
+  /// A hash of the structure of the circuit, which changes whenever its components, ports or names do.
+  /// Input values are left out, as they change while simulating.
+  pub fn fingerprint(&self) -> u64 {
+    let mut data = vec![];
+    self.write_body(&mut Writer { out: &mut data }, false).unwrap();
+    source_hash(&data)
+  }
+  pub fn write_binary<W: Write>(&self, out: W, hash: u64) -> io::Result<()> {
+    let mut w = Writer { out };
+    w.out.write_all(MAGIC)?;
+    w.u32(VERSION as usize)?;
+    w.out.write_all(&hash.to_le_bytes())?;
+    self.write_body(&mut w, true)
+  }
+  fn write_body<W: Write>(&self, w: &mut Writer<W>, input_values: bool) -> io::Result<()> {
+    w.u32(self.components().len())?;
+    for (id, (component, init)) in self.components().iter().enumerate() {
+      let tag = match component {
+        Component::Source(_) => 0,
+        Component::Buffer(_) => 1,
+        Component::Inverter(_) => 2,
+        Component::Or(_) => 3,
+        Component::And(_) => 4,
+        Component::Nor(_) => 5,
+        Component::Nand(_) => 6,
+        Component::Bus(_, _) => 7,
+        Component::Clock { .. } => 8,
+        Component::Xor(_, _) => 9,
+        Component::Xnor(_, _) => 10,
+        Component::Mux(_, _) => 11,
+        Component::Decoder(_, _) => 12,
+        Component::Encoder(_, _) => 13,
+        Component::Lut(_, _) => 14,
+        Component::Ram(_, _, _) => 15,
+      };
+      w.out.write_all(&[tag, *init as u8])?;
+      match component {
+        Component::Source(value) => w.out.write_all(&[(*value && (input_values || !self.inputs().contains(&id))) as u8])?,
+        Component::Bus(mode, _) => {
+          w.out.write_all(&[*mode as u8])?;
+          w.wires(&component.inputs())?;
+        },
+        Component::Or(_) | Component::And(_) | Component::Nor(_) | Component::Nand(_) => w.wires(&component.inputs())?,
+        Component::Clock { period, high, phase } => [*period, *high, *phase].iter().try_for_each(|n|w.u32(*n))?,
+        Component::Mux(data, select) => {
+          w.wires(data)?;
+          w.wires(select)?;
+        },
+        Component::Decoder(inputs, n) | Component::Encoder(inputs, n) => {
+          w.wires(inputs)?;
+          w.u32(*n)?;
+        },
+        Component::Lut(inputs, table) => {
+          w.wires(inputs)?;
+          w.u32(table.len())?;
+          w.out.write_all(&table.iter().map(|b|*b as u8).collect::<Vec<_>>())?;
+        },
+        Component::Ram(memory, address, bit) => {
+          w.u32(*memory)?;
+          w.wires(address)?;
+          w.u32(*bit)?;
+        },
+        component => component.inputs().iter().try_for_each(|i|w.u32(*i))?,
+      }
+    }
+    w.u32(self.memories().len())?;
+    for memory in self.memories() {
+      w.wires(&memory.address)?;
+      w.wires(&memory.data)?;
+      w.u32(memory.write)?;
+      w.u32(memory.clock.map_or(0, |c|c + 1))?;
+      w.u32(memory.init.len())?;
+      memory.init.iter().try_for_each(|word|w.u64(*word))?;
+    }
+    w.wires(self.inputs())?;
+    w.wires(self.outputs())?;
+    w.u32(self.instances().len())?;
+    for instance in self.instances() {
+      w.str(&instance.func)?;
+      w.str(&instance.label)?;
+      w.u32(instance.parent.map_or(0, |p|p + 1))?;
+      w.u32(instance.names.len())?;
+      for (name, wire) in &instance.names {
+        w.str(name)?;
+        w.u32(*wire)?;
+      }
+    }
+    for id in 0..self.components().len() {
+      w.u32(self.owner(id))?;
+    }
+    Ok(())
+  }
+  /// Reads a circuit written by `write_binary`, failing if it was written from a different source.
+  pub fn read_binary(data: &[u8], hash: u64) -> Result<Circuit, String> {
+    let mut r = Reader { data };
+    if r.bytes(4)? != MAGIC {
+      return Err("Not a circuit cache".to_owned());
+    }
+    let version = r.u32()?;
+    if version != VERSION as usize {
+      return Err(format!("Unsupported cache version {}, expected {}", version, VERSION));
+    }
+    if r.u64()? != hash {
+      return Err("The cache is out of date".to_owned());
+    }
+    let len = r.u32()?;
+    let mut components = Vec::with_capacity(len.min(data.len()));
+    for _ in 0..len {
+      let tag = r.u8()?;
+      let init = r.u8()? != 0;
+      let component = match tag {
+        0 => Component::Source(r.u8()? != 0),
+        1 => Component::Buffer(r.wire(len)?),
+        2 => Component::Inverter(r.wire(len)?),
+        3..=6 => {
+          let wires = r.wires(len)?.into_boxed_slice();
+          if wires.is_empty() {
+            return Err("A gate has no inputs".to_owned());
+          }
+          match tag {
+            3 => Component::Or(wires),
+            4 => Component::And(wires),
+            5 => Component::Nor(wires),
+            _ => Component::Nand(wires),
+          }
+        },
+        9 => Component::Xor(r.wire(len)?, r.wire(len)?),
+        10 => Component::Xnor(r.wire(len)?, r.wire(len)?),
+        11 => Component::Mux(r.wires(len)?.into(), r.wires(len)?.into()),
+        12 => Component::Decoder(r.wires(len)?.into(), r.u32()?),
+        13 => Component::Encoder(r.wires(len)?.into(), r.u32()?),
+        14 => {
+          let inputs = r.wires(len)?;
+          let count = r.u32()?;
+          Component::Lut(inputs.into(), r.bytes(count)?.iter().map(|b|*b != 0).collect())
+        },
+        15 => Component::Ram(r.u32()?, r.wires(len)?.into(), r.u32()?),
+        7 => {
+          let mode = *BusMode::ALL.get(r.u8()? as usize).ok_or("Unknown bus mode")?;
+          let wires = r.wires(len)?;
+          if wires.len() % 2 != 0 {
+            return Err("A bus has an unpaired driver".to_owned());
+          }
+          Component::Bus(mode, wires.chunks(2).map(|pair|(pair[0], pair[1])).collect())
+        },
+        8 => {
+          let (period, high, phase) = (r.u32()?, r.u32()?, r.u32()?);
+          if period == 0 || high > period {
+            return Err("Invalid clock".to_owned());
+          }
+          Component::Clock { period, high, phase }
+        },
+        tag => return Err(format!("Unknown component tag {}", tag)),
+      };
+      components.push((component, init));
+    }
+    let count = r.u32()?;
+    let mut memories = Vec::with_capacity(count.min(data.len()));
+    for _ in 0..count {
+      let (address, data, write) = (r.wires(len)?.into(), r.wires(len)?.into(), r.wire(len)?);
+      let clock = match r.u32()? {
+        0 => None,
+        c if c <= len => Some(c - 1),
+        _ => return Err("Invalid wire".to_owned()),
+      };
+      let init = (0..r.u32()?.min(1 << Memory::MAX_ADDRESS)).map(|_|r.u64()).collect::<Result<_, _>>()?;
+      memories.push(Memory { address, data, write, clock, init });
+    }
+    check_memories(&components, &memories)?;
+    let inputs = r.wires(len)?;
+    if inputs.iter().any(|i|!matches!(components[*i].0, Component::Source(_))) {
+      return Err("An input is not a source".to_owned());
+    }
+    let outputs = r.wires(len)?;
+    let count = r.u32()?;
+    let mut instances = Vec::with_capacity(count.min(data.len()));
+    for i in 0..count {
+      let func = r.str()?;
+      let label = r.str()?;
+      let parent = match r.u32()? {
+        0 => None,
+        p if p <= i => Some(p - 1),
+        _ => return Err("Invalid instance parent".to_owned()),
+      };
+      let names = (0..r.u32()?).map(|_|Ok((r.str()?, r.wire(len)?))).collect::<Result<_, String>>()?;
+      instances.push(Instance { func, label, parent, names });
+    }
+    if instances.is_empty() {
+      return Err("The circuit has no top level instance".to_owned());
+    }
+    let owners = (0..len).map(|_|r.wire(count)).collect::<Result<_, _>>()?;
+    Ok(Circuit::from_parts(WholeNew { components: components.into_boxed_slice(), memories: memories.into_boxed_slice() }, owners, instances, inputs, outputs))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{ source_hash, VERSION };
+  use crate::circuit::Circuit;
+  use crate::netlist::tests::every_component;
+
+  fn write(circuit: &Circuit, hash: u64) -> Vec<u8> {
+    let mut data = vec![];
+    circuit.write_binary(&mut data, hash).unwrap();
+    data
+  }
+
+  #[test]
+  fn round_trip() {
+    let mut circuit = every_component();
+    circuit.set_input(vec![true, false]).unwrap();
+    let read = Circuit::read_binary(&write(&circuit, 7), 7).unwrap();
+    assert_eq!(read.to_json(), circuit.to_json());
+    assert_eq!(read.get_input(), [true, false]);
+  }
+
+  #[test]
+  fn fingerprint() {
+    let mut circuit = every_component();
+    let fingerprint = circuit.fingerprint();
+    circuit.set_input(vec![false, false]).unwrap();
+    assert_eq!(circuit.fingerprint(), fingerprint);
+    let mut builder = Circuit::builder();
+    let slot = builder.new_slot();
+    builder.add_input(slot, false);
+    assert_ne!(builder.build().fingerprint(), fingerprint);
+  }
+
+  #[test]
+  fn source_hash_is_fnv() {
+    assert_eq!(source_hash(b""), 0xcbf2_9ce4_8422_2325);
+    assert_eq!(source_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+  }
+
+  #[test]
+  fn errors() {
+    let data = write(&every_component(), 7);
+    let read_err = |data: &[u8], hash: u64|Circuit::read_binary(data, hash).err().unwrap();
+    assert_eq!(read_err(b"JUNK", 7), "Not a circuit cache");
+    let mut old = data.clone();
+    old[4..8].copy_from_slice(&(VERSION - 1).to_le_bytes());
+    assert_eq!(read_err(&old, 7), format!("Unsupported cache version {}, expected {}", VERSION - 1, VERSION));
+    assert_eq!(read_err(&data, 8), "The cache is out of date");
+    assert_eq!(read_err(&data[..data.len() - 1], 7), "Unexpected end of file");
+    let mut tagged = data.clone();
+    tagged[20] = 99;
+    assert_eq!(read_err(&tagged, 7), "Unknown component tag 99");
+    let mut wired = data;
+    // After the two 3 byte sources comes a buffer, whose input follows its tag and initial value.
+    wired[20 + 2 * 3 + 2..][..4].copy_from_slice(&1000u32.to_le_bytes());
+    assert_eq!(read_err(&wired, 7), "Invalid wire");
+  }
+}
@@ -1,18 +1,62 @@
-use crate::base::{Data, Component, WholeNew, WholeNewState};
+use crate::base::{Data, Component, Memory, WholeNew, WholeNewState};
 use crate::slot_vec::SlotVec;
 
+#[derive(Debug)]
+pub struct Instance {
+  pub func: String,
+  pub label: String,
+  pub parent: Option<usize>,
+  pub names: Vec<(String, usize)>,
+}
+
 pub struct Builder {
   components: SlotVec<(Component, Data)>,
+  memories: Vec<Memory>,
+  owners: Vec<usize>,
+  instances: Vec<Instance>,
+  scope: Vec<usize>,
   inputs: Vec<usize>,
   outputs: Vec<usize>,
 }
 
 impl Builder {
   pub fn new_slot(&mut self) -> usize {
+    self.owners.push(0);
     self.components.new_slot()
   }
   pub fn place_component(&mut self, slot: usize, component: Component, default: bool) {
     self.components.fill_slot(slot, (component, default));
+    self.owners[slot] = *self.scope.last().unwrap();
+  }
+  pub fn add_component(&mut self, component: Component, default: bool) -> usize {
+    let slot = self.new_slot();
+    self.place_component(slot, component, default);
+    slot
+  }
+  /// Adds a RAM, returning the index its data bits refer to.
+  pub fn add_memory(&mut self, memory: Memory) -> usize {
+    self.memories.push(memory);
+    self.memories.len() - 1
+  }
+  /// Places a pulse of a few ticks following every rising edge of `clock`.
+  pub fn add_rising_edge(&mut self, clock: usize) -> usize {
+    let delayed = self.add_component(Component::Inverter(clock), true);
+    let delayed = self.add_component(Component::Buffer(delayed), true);
+    self.add_component(Component::And([clock, delayed].into()), false)
+  }
+  /// Returns the set and reset signals of a D latch, which follows `d` while `enable` is high.
+  pub fn add_d_latch_input(&mut self, d: usize, enable: usize) -> (usize, usize) {
+    let nd = self.add_component(Component::Inverter(d), true);
+    let set = self.add_component(Component::And([enable, d].into()), false);
+    let reset = self.add_component(Component::And([enable, nd].into()), false);
+    (set, reset)
+  }
+  /// Places a NOR based SR latch with its output in `q`, and returns the inverted output.
+  pub fn place_sr_latch(&mut self, q: usize, set: usize, reset: usize, init: bool) -> usize {
+    let qn = self.new_slot();
+    self.place_component(q, Component::Nor([reset, qn].into()), init);
+    self.place_component(qn, Component::Nor([set, q].into()), !init);
+    qn
   }
   pub fn add_input(&mut self, slot: usize, default: bool) {
     self.place_component(slot, Component::Source(default), default);
@@ -23,13 +67,40 @@ impl Builder {
   }
   pub fn add_bus_input(&mut self, bus: usize, high: usize, low: usize) {
     match &mut self.components[bus] {
-      (Component::Bus(inputs), _) => inputs.push((high, low)),
+      (Component::Bus(_, inputs), _) => inputs.push((high, low)),
       _ => panic!("Not a bus"),
     }
   }
+  pub fn set_name(&mut self, name: &str) {
+    self.instances[0].func = name.to_owned();
+    self.instances[0].label = name.to_owned();
+  }
+  pub fn enter(&mut self, func: &str) {
+    let parent = *self.scope.last().unwrap();
+    let index = self.instances.iter().filter(|i|i.parent == Some(parent) && i.func == func).count();
+    self.scope.push(self.instances.len());
+    self.instances.push(Instance {
+      func: func.to_owned(),
+      label: format!("{}#{}", func, index),
+      parent: Some(parent),
+      names: vec![],
+    });
+  }
+  pub fn exit(&mut self) {
+    if self.scope.len() == 1 {
+      panic!("Can't exit the top level instance")
+    }
+    self.scope.pop();
+  }
+  pub fn name_wire(&mut self, wire: usize, name: String) {
+    let scope = *self.scope.last().unwrap();
+    self.instances[scope].names.push((name, wire));
+  }
   pub fn build(self) -> Circuit {
     Circuit {
-      whole_new: WholeNew { components: self.components.build() },
+      whole_new: WholeNew { components: self.components.build(), memories: self.memories.into_boxed_slice() },
+      owners: self.owners.into_boxed_slice(),
+      instances: self.instances.into_boxed_slice(),
       inputs: self.inputs.into_boxed_slice(),
       outputs: self.outputs.into_boxed_slice(),
     }
@@ -39,6 +110,8 @@ impl Builder {
 #[derive(Debug)]
 pub struct Circuit {
   whole_new: WholeNew,
+  owners: Box<[usize]>,
+  instances: Box<[Instance]>,
   inputs: Box<[usize]>,
   outputs: Box<[usize]>,
 }
@@ -47,16 +120,103 @@ impl Circuit {
   pub fn builder() -> Builder {
     Builder {
       components: SlotVec::new(),
+      memories: Vec::new(),
+      owners: Vec::new(),
+      instances: vec![Instance { func: "circuit".to_owned(), label: "circuit".to_owned(), parent: None, names: vec![] }],
+      scope: vec![0],
       inputs: Vec::new(),
       outputs: Vec::new(),
     }
   }
+  pub(crate) fn from_parts(whole_new: WholeNew, owners: Vec<usize>, instances: Vec<Instance>, inputs: Vec<usize>, outputs: Vec<usize>) -> Circuit {
+    Circuit {
+      whole_new,
+      owners: owners.into_boxed_slice(),
+      instances: instances.into_boxed_slice(),
+      inputs: inputs.into_boxed_slice(),
+      outputs: outputs.into_boxed_slice(),
+    }
+  }
+  pub fn components(&self) -> &[(Component, Data)] {
+    &self.whole_new.components
+  }
+  pub fn memories(&self) -> &[Memory] {
+    &self.whole_new.memories
+  }
+  pub fn inputs(&self) -> &[usize] {
+    &self.inputs
+  }
+  pub fn outputs(&self) -> &[usize] {
+    &self.outputs
+  }
+  pub fn instances(&self) -> &[Instance] {
+    &self.instances
+  }
+  pub fn owner(&self, component: usize) -> usize {
+    self.owners[component]
+  }
+  pub fn name(&self) -> &str {
+    &self.instances[0].func
+  }
+  /// The dotted path of an instance relative to the top level, which has an empty path.
+  pub fn instance_path(&self, instance: usize) -> String {
+    match self.instances[instance].parent {
+      None => String::new(),
+      Some(parent) => {
+        let mut path = self.instance_path(parent);
+        if !path.is_empty() {
+          path.push('.');
+        }
+        path.push_str(&self.instances[instance].label);
+        path
+      },
+    }
+  }
+  /// Every wire name, qualified with the path of the instance it was named in.
+  pub fn wire_names(&self) -> Vec<(String, usize)> {
+    let mut names = vec![];
+    for (i, instance) in self.instances.iter().enumerate() {
+      let path = self.instance_path(i);
+      for (name, wire) in &instance.names {
+        let name = if path.is_empty() { name.clone() } else { format!("{}.{}", path, name) };
+        names.push((name, *wire));
+      }
+    }
+    names
+  }
+  /// Finds a wire by its qualified name.
+  pub fn find_wire(&self, name: &str) -> Option<usize> {
+    self.wire_names().into_iter().find(|(n, _)|n == name).map(|(_, wire)|wire)
+  }
+  pub fn port_name(&self, wire: usize) -> Option<&str> {
+    self.instances[0].names.iter().find(|(_, w)|*w == wire).map(|(name, _)|name.as_str())
+  }
+  /// Finds a port by name, as whether it is an input and its index among the inputs or outputs.
+  pub fn find_port(&self, name: &str) -> Option<(bool, usize)> {
+    let index = |ports: &[usize]|ports.iter().position(|wire|self.port_name(*wire) == Some(name));
+    index(&self.inputs).map(|i|(true, i)).or_else(||index(&self.outputs).map(|i|(false, i)))
+  }
   pub fn new_state(&mut self) -> WholeNewState {
     self.whole_new.new_state()
   }
   pub fn update(&mut self, state: &mut WholeNewState) {
     self.whole_new.update(state);
   }
+  pub fn input_count(&self) -> usize {
+    self.inputs.len()
+  }
+  pub fn output_count(&self) -> usize {
+    self.outputs.len()
+  }
+  pub fn get_input(&self) -> Vec<bool> {
+    self.inputs.iter().map(|i|match self.whole_new.components[*i].0 {
+      Component::Source(v) => v,
+      _ => unreachable!(),
+    }).collect()
+  }
+  pub fn get_output(&self, state: &WholeNewState) -> Vec<bool> {
+    self.outputs.iter().map(|i|state.components[*i]).collect()
+  }
   pub fn set_input(&mut self, inputs: Vec<bool>) -> Result<(), String> {
     if self.inputs.len() != inputs.len() {
       return Err(format!("Expected {} inputs, but recieved {}", self.inputs.len(), inputs.len()))
@@ -0,0 +1,97 @@
+use std::io::{ self, Write };
+use crate::base::{ BusMode, Component };
+use crate::circuit::Circuit;
+
+/// The shape and label of a component. Circuit inputs and outputs are drawn as `invhouse` and `house`, so no component uses those.
+fn shape(component: &Component) -> (&'static str, &'static str) {
+  match component {
+    Component::Source(_) => ("plaintext", "source"),
+    Component::Buffer(_) => ("triangle", "buffer"),
+    Component::Inverter(_) => ("invtriangle", "not"),
+    Component::Or(_) => ("ellipse", "or"),
+    Component::And(_) => ("box", "and"),
+    Component::Nor(_) => ("Mcircle", "nor"),
+    Component::Nand(_) => ("Msquare", "nand"),
+    Component::Xor(_, _) => ("diamond", "xor"),
+    Component::Xnor(_, _) => ("Mdiamond", "xnor"),
+    Component::Bus(BusMode::Float, _) => ("hexagon", "bus"),
+    Component::Bus(BusMode::PullUp, _) => ("hexagon", "pullup bus"),
+    Component::Bus(BusMode::PullDown, _) => ("hexagon", "pulldown bus"),
+    Component::Bus(BusMode::OpenDrain, _) => ("hexagon", "open drain bus"),
+    Component::Clock { .. } => ("doublecircle", "clock"),
+    Component::Mux(_, _) => ("trapezium", "mux"),
+    Component::Decoder(_, _) => ("invtrapezium", "decoder"),
+    Component::Encoder(_, _) => ("trapezium", "encoder"),
+    Component::Lut(_, _) => ("box3d", "lut"),
+    Component::Ram(_, _, _) => ("box3d", "ram"),
+  }
+}
+
+impl Circuit {
+  fn write_dot_node<W: Write>(&self, out: &mut W, id: usize, indent: &str) -> io::Result<()> {
+    let (component, _) = &self.components()[id];
+    if let Some(port) = self.inputs().iter().position(|i|*i == id) {
+      let name = self.port_name(id).map_or_else(||format!("in{}", port), str::to_owned);
+      return writeln!(out, "{}c{} [label=\"{}\", shape=invhouse];", indent, id, name);
+    }
+    let (shape, mut label) = shape(component);
+    if let Component::Source(value) = component {
+      label = if *value { "1" } else { "0" };
+    }
+    let names = &self.instances()[self.owner(id)].names;
+    match names.iter().find(|(_, w)|*w == id) {
+      Some((name, _)) => writeln!(out, "{}c{} [label=\"{}\\n{}\", shape={}];", indent, id, label, name, shape),
+      None => writeln!(out, "{}c{} [label=\"{}\", shape={}];", indent, id, label, shape),
+    }
+  }
+  fn write_dot_cluster<W: Write>(&self, out: &mut W, instance: usize, depth: usize) -> io::Result<()> {
+    let indent = "  ".repeat(depth + 1);
+    if instance != 0 {
+      writeln!(out, "{}subgraph cluster_{} {{", "  ".repeat(depth), instance)?;
+      writeln!(out, "{}label=\"{}\";", indent, self.instances()[instance].label)?;
+    }
+    for id in (0..self.components().len()).filter(|c|self.owner(*c) == instance) {
+      self.write_dot_node(out, id, &indent)?;
+    }
+    for child in (0..self.instances().len()).filter(|i|self.instances()[*i].parent == Some(instance)) {
+      self.write_dot_cluster(out, child, depth + 1)?;
+    }
+    if instance != 0 {
+      writeln!(out, "{}}}", "  ".repeat(depth))?;
+    }
+    Ok(())
+  }
+  /// Writes the gate level netlist as a Graphviz graph, optionally grouping the gates of every function call in a cluster.
+  pub fn write_dot<W: Write>(&self, mut out: W, cluster: bool) -> io::Result<()> {
+    writeln!(out, "digraph \"{}\" {{", self.name())?;
+    writeln!(out, "  rankdir=LR;")?;
+    if cluster {
+      self.write_dot_cluster(&mut out, 0, 0)?;
+    } else {
+      for id in 0..self.components().len() {
+        self.write_dot_node(&mut out, id, "  ")?;
+      }
+    }
+    for (port, id) in self.outputs().iter().enumerate() {
+      let name = self.port_name(*id).map_or_else(||format!("out{}", port), str::to_owned);
+      writeln!(out, "  out{} [label=\"{}\", shape=house];", port, name)?;
+      writeln!(out, "  c{} -> out{};", id, port)?;
+    }
+    for (id, (component, _)) in self.components().iter().enumerate() {
+      match component {
+        Component::Bus(_, inputs) => for (high, low) in inputs {
+          writeln!(out, "  c{} -> c{} [label=\"high\"];", high, id)?;
+          writeln!(out, "  c{} -> c{} [label=\"low\"];", low, id)?;
+        },
+        // Every bit of a RAM depends on its write ports too.
+        Component::Ram(memory, _, _) => for input in self.memories()[*memory].inputs() {
+          writeln!(out, "  c{} -> c{};", input, id)?;
+        },
+        component => for input in component.inputs() {
+          writeln!(out, "  c{} -> c{};", input, id)?;
+        },
+      }
+    }
+    writeln!(out, "}}")
+  }
+}
@@ -5,7 +5,7 @@ use std::hash::Hash;
 use std::iter::{ Extend, FromIterator };
 use std::fmt::Debug;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Env<T> {
     map: HashMap<String, T>,
 }
@@ -17,6 +17,13 @@ impl<T> Default for Env<T> {
 }
 
 impl<T> Env<T> {
+    pub fn get(&self, key: &str) -> Option<&T> {
+        self.map.get(key)
+    }
+    /// Inserts a value, replacing any with the same key.
+    pub fn replace(&mut self, key: String, val: T) {
+        self.map.insert(key, val);
+    }
     pub fn insert(&mut self, key: String, val: T) {
         if self.map.insert(key.clone(), val).is_some() {
             panic!("Duplicate key: {}", key);
@@ -27,7 +34,15 @@ impl<T> Env<T> {
 impl<T, I: ?Sized + Hash + Eq + Debug> Index<&I> for Env<T> where String: Borrow<I> {
     type Output = T;
     fn index(&self, i: &I) -> &T {
-        &self.map.get(i).unwrap_or_else(||panic!("Unknown key: {:?}", i))
+        self.map.get(i).unwrap_or_else(||panic!("Unknown key: {:?}", i))
+    }
+}
+
+impl<T> IntoIterator for Env<T> {
+    type Item = (String, T);
+    type IntoIter = std::collections::hash_map::IntoIter<String, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.into_iter()
     }
 }
 
@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::fmt::{ self, Display, Formatter, Write as _ };
+use std::io::{ self, Write };
+use crate::base::{ BusMode, Component, Data };
+use crate::circuit::Circuit;
+
+const MAX_INPUTS: usize = 16;
+
+#[derive(Debug)]
+pub struct StateGraph {
+  input_count: usize,
+  pub states: Vec<Box<[Data]>>,
+  pub outputs: Vec<Vec<Data>>,
+  pub transitions: Vec<Vec<Option<usize>>>,
+  pub complete: bool,
+  /// The wires that hold state, with their names.
+  pub state_wires: Vec<(String, usize)>,
+}
+
+fn write_bits(f: &mut dyn fmt::Write, bits: &[Data]) -> fmt::Result {
+  bits.iter().try_for_each(|b|f.write_char(if *b { '1' } else { '0' }))
+}
+
+fn bits(value: usize, count: usize) -> Vec<Data> {
+  (0..count).rev().map(|i|value >> i & 1 == 1).collect()
+}
+
+fn bit_string(bits: &[Data]) -> String {
+  let mut s = String::with_capacity(bits.len());
+  write_bits(&mut s, bits).unwrap();
+  s
+}
+
+impl Circuit {
+  /// The wires that hold state, those that feed back into themselves through a loop of components.
+  fn state_wires(&self) -> Vec<usize> {
+    let count = self.components().len();
+    let inputs: Vec<Vec<usize>> = self.components().iter().map(|(c, _)|c.inputs()).collect();
+    let mut outputs = vec![vec![]; count];
+    for (wire, inputs) in inputs.iter().enumerate() {
+      for input in inputs {
+        outputs[*input].push(wire);
+      }
+    }
+    // Kosaraju's algorithm: order the wires by when their search finishes, then search backwards in reverse order.
+    let mut order = Vec::with_capacity(count);
+    let mut visited = vec![false; count];
+    for start in 0..count {
+      if visited[start] {
+        continue;
+      }
+      visited[start] = true;
+      let mut stack = vec![(start, 0)];
+      while let Some(&(wire, edge)) = stack.last() {
+        match outputs[wire].get(edge) {
+          Some(&next) => {
+            stack.last_mut().unwrap().1 += 1;
+            if !visited[next] {
+              visited[next] = true;
+              stack.push((next, 0));
+            }
+          },
+          None => {
+            order.push(wire);
+            stack.pop();
+          },
+        }
+      }
+    }
+    let mut loops = vec![usize::MAX; count];
+    let mut sizes = vec![];
+    for start in order.into_iter().rev() {
+      if loops[start] != usize::MAX {
+        continue;
+      }
+      let id = sizes.len();
+      loops[start] = id;
+      let mut stack = vec![start];
+      let mut size = 0;
+      while let Some(wire) = stack.pop() {
+        size += 1;
+        for input in &inputs[wire] {
+          if loops[*input] == usize::MAX {
+            loops[*input] = id;
+            stack.push(*input);
+          }
+        }
+      }
+      sizes.push(size);
+    }
+    (0..count).filter(|wire|sizes[loops[*wire]] > 1 || inputs[*wire].contains(wire)).collect()
+  }
+  pub fn explore(&mut self, limit: usize) -> Result<StateGraph, String> {
+    let input_count = self.input_count();
+    if input_count > MAX_INPUTS {
+      return Err(format!("Can't explore a circuit with {} inputs, the limit is {}", input_count, MAX_INPUTS));
+    }
+    if !self.memories().is_empty() {
+      return Err("Can't explore a circuit with RAM, as its contents are not part of the explored states".to_owned());
+    }
+    if self.components().iter().any(|(c, _)|matches!(c, Component::Clock { .. })) {
+      return Err("Can't explore a circuit with a clock, as its value depends on the step rather than the state".to_owned());
+    }
+    if self.components().iter().any(|(c, _)|matches!(c, Component::Bus(BusMode::Float, _))) {
+      return Err("Can't explore a circuit with a float bus, as it reads a random value while undriven".to_owned());
+    }
+    let names = self.wire_names();
+    let state_wires = self.state_wires().into_iter().map(|wire|{
+      let name = names.iter().find(|(_, w)|*w == wire).map_or_else(||wire.to_string(), |(name, _)|name.clone());
+      (name, wire)
+    }).collect();
+    let saved = self.get_input();
+    let mut state = self.new_state();
+    let mut graph = StateGraph { input_count, states: vec![], outputs: vec![], transitions: vec![], complete: true, state_wires };
+    let mut ids = HashMap::new();
+    ids.insert(state.components.clone(), 0);
+    graph.states.push(state.components.clone());
+    graph.outputs.push(self.get_output(&state));
+    let mut next = 0;
+    while next < graph.states.len() {
+      let mut transitions = Vec::with_capacity(1 << input_count);
+      for input in 0..1 << input_count {
+        self.set_input(bits(input, input_count))?;
+        state.components.copy_from_slice(&graph.states[next]);
+        self.update(&mut state);
+        transitions.push(match ids.get(&state.components) {
+          Some(id) => Some(*id),
+          None if graph.states.len() < limit => {
+            let id = graph.states.len();
+            ids.insert(state.components.clone(), id);
+            graph.states.push(state.components.clone());
+            graph.outputs.push(self.get_output(&state));
+            Some(id)
+          },
+          None => {
+            graph.complete = false;
+            None
+          },
+        });
+      }
+      graph.transitions.push(transitions);
+      next += 1;
+    }
+    self.set_input(saved)?;
+    Ok(graph)
+  }
+}
+
+impl StateGraph {
+  pub fn input_count(&self) -> usize {
+    self.input_count
+  }
+  /// Returns every (state, input) pair where holding the input constant never lets the state settle.
+  pub fn oscillating(&self) -> Vec<(usize, usize)> {
+    let mut result = vec![];
+    for input in 0..1 << self.input_count {
+      // 0: unvisited, 1: visiting, 2: settles, 3: oscillates, 4: leaves the explored graph
+      let mut mark = vec![0u8; self.states.len()];
+      for start in 0..self.states.len() {
+        let mut path = vec![];
+        let mut current = start;
+        let verdict = loop {
+          match mark[current] {
+            0 => {
+              mark[current] = 1;
+              path.push(current);
+              match self.transitions[current][input] {
+                Some(next) if next == current => break 2,
+                Some(next) => current = next,
+                None => break 4,
+              }
+            },
+            1 => break 3,
+            m => break m,
+          }
+        };
+        for state in path {
+          mark[state] = verdict;
+        }
+      }
+      result.extend(mark.into_iter().enumerate().filter(|(_, m)|*m == 3).map(|(state, _)|(state, input)));
+    }
+    result.sort_unstable();
+    result
+  }
+  /// Returns the values of the state wires that no explored state holds, or None if there are too many to list.
+  pub fn unreachable_states(&self) -> Option<Vec<Vec<Data>>> {
+    let count = self.state_wires.len();
+    if count > MAX_INPUTS {
+      return None;
+    }
+    let mut seen = vec![false; 1 << count];
+    for state in &self.states {
+      seen[self.state_wires.iter().fold(0, |acc, (_, wire)|acc << 1 | state[*wire] as usize)] = true;
+    }
+    Some(seen.into_iter().enumerate().filter(|(_, s)|!s).map(|(i, _)|bits(i, count)).collect())
+  }
+  pub fn write_dot<W: Write>(&self, mut out: W) -> io::Result<()> {
+    let oscillating = self.oscillating();
+    writeln!(out, "digraph states {{")?;
+    for (id, output) in self.outputs.iter().enumerate() {
+      let style = if oscillating.iter().any(|(s, _)|*s == id) { ", color=red" } else { "" };
+      let shape = if id == 0 { "doublecircle" } else { "circle" };
+      writeln!(out, "  s{} [label=\"s{}\\n{}\", shape={}{}];", id, id, bit_string(output), shape, style)?;
+    }
+    for (from, transitions) in self.transitions.iter().enumerate() {
+      let mut edges: Vec<(usize, Vec<String>)> = vec![];
+      for (input, to) in transitions.iter().enumerate() {
+        let to = match to {
+          Some(to) => to,
+          None => continue,
+        };
+        let label = bit_string(&bits(input, self.input_count));
+        match edges.iter_mut().find(|(t, _)|t == to) {
+          Some((_, labels)) => labels.push(label),
+          None => edges.push((*to, vec![label])),
+        }
+      }
+      for (to, labels) in edges {
+        writeln!(out, "  s{} -> s{} [label=\"{}\"];", from, to, labels.join(","))?;
+      }
+    }
+    writeln!(out, "}}")
+  }
+}
+
+impl Display for StateGraph {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    let oscillating = self.oscillating();
+    write!(f, "state\toutput")?;
+    for input in 0..1 << self.input_count {
+      f.write_char('\t')?;
+      write_bits(f, &bits(input, self.input_count))?;
+    }
+    writeln!(f)?;
+    for (id, (output, transitions)) in self.outputs.iter().zip(&self.transitions).enumerate() {
+      write!(f, "s{}\t", id)?;
+      write_bits(f, output)?;
+      for (input, to) in transitions.iter().enumerate() {
+        match to {
+          Some(to) => write!(f, "\ts{}", to)?,
+          None => f.write_str("\t?")?,
+        }
+        if oscillating.contains(&(id, input)) {
+          f.write_char('~')?;
+        }
+      }
+      writeln!(f)?;
+    }
+    if !oscillating.is_empty() {
+      writeln!(f, "~ never settles while the input is held")?;
+    }
+    if !self.complete {
+      writeln!(f, "? not explored, the limit of {} states was reached", self.states.len())?;
+    }
+    if let Some(unreachable) = self.unreachable_states().filter(|_|self.complete) {
+      if !unreachable.is_empty() {
+        let names: Vec<_> = self.state_wires.iter().map(|(name, _)|name.as_str()).collect();
+        write!(f, "Unreachable states of {}:", names.join(" "))?;
+        for state in unreachable {
+          f.write_char(' ')?;
+          write_bits(f, &state)?;
+        }
+        writeln!(f)?;
+      }
+    }
+    Ok(())
+  }
+}
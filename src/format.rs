@@ -0,0 +1,286 @@
+use std::fmt::{ self, Display, Formatter };
+use crate::base::{ Data, WholeNewState };
+use crate::circuit::Circuit;
+
+/// How the value of a group of ports is shown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+  Bin,
+  Hex,
+  Unsigned,
+  Signed,
+}
+
+impl Format {
+  pub fn parse(s: &str) -> Result<Format, String> {
+    match s {
+      "bin" => Ok(Format::Bin),
+      "hex" => Ok(Format::Hex),
+      "unsigned" => Ok(Format::Unsigned),
+      "signed" => Ok(Format::Signed),
+      s => Err(format!("Unknown format: {}", s)),
+    }
+  }
+  /// Formats bits given least significant first.
+  pub fn format(self, bits: &[Data]) -> String {
+    let value = bits.iter().rev().fold(0u64, |acc, b|acc << 1 | *b as u64);
+    match self {
+      Format::Bin => bits.iter().rev().map(|b|if *b { '1' } else { '0' }).collect(),
+      Format::Hex => format!("0x{:0width$X}", value, width = bits.len().div_ceil(4)),
+      Format::Unsigned => value.to_string(),
+      Format::Signed => match bits.last() {
+        Some(true) => format!("-{}", (!value).wrapping_add(1) & mask(bits.len())),
+        _ => value.to_string(),
+      },
+    }
+  }
+}
+
+impl Display for Format {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match self {
+      Format::Bin => write!(f, "bin"),
+      Format::Hex => write!(f, "hex"),
+      Format::Unsigned => write!(f, "unsigned"),
+      Format::Signed => write!(f, "signed"),
+    }
+  }
+}
+
+fn mask(width: usize) -> u64 {
+  if width >= 64 { !0 } else { (1 << width) - 1 }
+}
+
+/// Parses a decimal, `0x` hex or `0b` binary number, which may be negative, into `width` bits least significant first.
+pub fn parse_value(s: &str, width: usize) -> Result<Vec<Data>, String> {
+  let (negative, digits) = match s.strip_prefix('-') {
+    Some(digits) => (true, digits),
+    None => (false, s),
+  };
+  let value = if let Some(hex) = digits.strip_prefix("0x").or_else(||digits.strip_prefix("0X")) {
+    u64::from_str_radix(hex, 16)
+  } else if let Some(bin) = digits.strip_prefix("0b").or_else(||digits.strip_prefix("0B")) {
+    u64::from_str_radix(bin, 2)
+  } else {
+    digits.parse()
+  }.map_err(|_|format!("Not a number: {}", s))?;
+  let fits = if negative { value <= 1 << (width - 1) } else { value & !mask(width) == 0 };
+  if !fits {
+    return Err(format!("{} does not fit in {} bits", s, width));
+  }
+  let value = if negative { value.wrapping_neg() } else { value };
+  Ok((0..width).map(|i|value >> i & 1 == 1).collect())
+}
+
+/// Splits a name like `o7` into its prefix and trailing number.
+fn split_index(name: &str) -> Option<(&str, usize)> {
+  let i = name.trim_end_matches(|c: char|c.is_ascii_digit()).len();
+  name[i..].parse().ok().map(|n|(&name[..i], n))
+}
+
+/// Expands a port range such as `o0..o7` into `o0`, `o1`, ..., `o7`. Other names are returned as is.
+fn expand(spec: &str) -> Result<Vec<String>, String> {
+  let (first, last) = match spec.find("..") {
+    Some(i) => (&spec[..i], &spec[i + 2..]),
+    None => return Ok(vec![spec.to_owned()]),
+  };
+  let split = |name| split_index(name).ok_or_else(||format!("Invalid range: {}", spec));
+  let ((prefix, a), (last_prefix, b)) = (split(first)?, split(last)?);
+  if prefix != last_prefix {
+    return Err(format!("Invalid range: {}", spec));
+  }
+  let range: Vec<usize> = if a <= b { (a..=b).collect() } else { (b..=a).rev().collect() };
+  Ok(range.into_iter().map(|n|format!("{}{}", prefix, n)).collect())
+}
+
+/// Ports shown and set together as one number.
+#[derive(Debug)]
+pub struct Group {
+  pub name: String,
+  /// Whether the ports are inputs rather than outputs.
+  pub input: bool,
+  /// Indices into the circuit's inputs or outputs, least significant first.
+  pub bits: Vec<usize>,
+  pub format: Format,
+}
+
+impl Display for Group {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    let kind = if self.input { "input" } else { "output" };
+    write!(f, "{}: {} {} bits as {}", self.name, self.bits.len(), kind, self.format)
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct Groups {
+  pub list: Vec<Group>,
+}
+
+impl Groups {
+  /// Adds a group of ports, least significant first, replacing any group with the same name.
+  pub fn add(&mut self, name: &str, ports: &[&str], format: Format, circuit: &Circuit) -> Result<(), String> {
+    let mut input = None;
+    let mut bits = vec![];
+    for port in ports.iter().map(|p|expand(p)).collect::<Result<Vec<_>, _>>()?.concat() {
+      let (is_input, index) = circuit.find_port(&port).ok_or_else(||format!("No port named {}", port))?;
+      if *input.get_or_insert(is_input) != is_input {
+        return Err(format!("{} mixes inputs and outputs", name));
+      }
+      bits.push(index);
+    }
+    let input = input.ok_or("A group needs at least one port")?;
+    if bits.len() > 64 {
+      return Err(format!("{} has {} bits, but groups may have at most 64", name, bits.len()));
+    }
+    self.remove(name);
+    self.list.push(Group { name: name.to_owned(), input, bits, format });
+    Ok(())
+  }
+  pub fn remove(&mut self, name: &str) -> bool {
+    let len = self.list.len();
+    self.list.retain(|g|g.name != name);
+    self.list.len() != len
+  }
+  pub fn get_mut(&mut self, name: &str) -> Result<&mut Group, String> {
+    self.list.iter_mut().find(|g|g.name == name).ok_or_else(||format!("No group named {}", name))
+  }
+  /// Applies assignments such as `a=0x2F` or `cin=1` to the current inputs.
+  /// The name is a group of inputs or a single input port.
+  pub fn assign(&self, circuit: &Circuit, assignments: &[&str]) -> Result<Vec<Data>, String> {
+    let mut input = circuit.get_input();
+    for assignment in assignments {
+      let (name, value) = assignment.split_at(assignment.find('=').ok_or_else(||format!("Expected <name>=<value>, recieved {}", assignment))?);
+      let bits = match self.list.iter().find(|g|g.name == name && g.input) {
+        Some(group) => group.bits.clone(),
+        None => match circuit.find_port(name) {
+          Some((true, index)) => vec![index],
+          _ => return Err(format!("No input named {}", name)),
+        },
+      };
+      for (index, bit) in bits.iter().zip(parse_value(&value[1..], bits.len())?) {
+        input[*index] = bit;
+      }
+    }
+    Ok(input)
+  }
+  /// Shows every output, with grouped outputs as numbers and the rest by name.
+  pub fn format_output(&self, circuit: &Circuit, state: &WholeNewState) -> String {
+    let output = circuit.get_output(state);
+    let mut items = vec![];
+    let mut grouped = vec![false; output.len()];
+    for group in self.list.iter().filter(|g|!g.input) {
+      let bits: Vec<_> = group.bits.iter().map(|i|output[*i]).collect();
+      group.bits.iter().for_each(|i|grouped[*i] = true);
+      items.push(format!("{}={}", group.name, group.format.format(&bits)));
+    }
+    for (i, bit) in output.iter().enumerate().filter(|(i, _)|!grouped[*i]) {
+      let name = circuit.port_name(circuit.outputs()[i]).map_or_else(||i.to_string(), str::to_owned);
+      items.push(format!("{}={}", name, *bit as u8));
+    }
+    items.join(" ")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{ expand, parse_value, Format, Groups };
+  use crate::base::Component;
+  use crate::circuit::Circuit;
+
+  const FORMATS: [Format; 4] = [Format::Bin, Format::Hex, Format::Unsigned, Format::Signed];
+
+  /// A circuit with inputs `a0` to `a3` and `c`, and outputs `o0` to `o3` copying `a0` to `a3`.
+  fn circuit() -> Circuit {
+    let mut b = Circuit::builder();
+    let names = ["a0", "a1", "a2", "a3", "c"];
+    let inputs: Vec<usize> = names.iter().map(|name|{
+      let slot = b.new_slot();
+      b.add_input(slot, false);
+      b.name_wire(slot, name.to_string());
+      slot
+    }).collect();
+    for (i, input) in inputs[..4].iter().enumerate() {
+      let output = b.add_component(Component::Buffer(*input), false);
+      b.add_output(output);
+      b.name_wire(output, format!("o{}", i));
+    }
+    b.build()
+  }
+
+  #[test]
+  fn round_trip() {
+    for format in FORMATS {
+      assert_eq!(Format::parse(&format.to_string()), Ok(format));
+      for width in [1, 3, 8, 64] {
+        for value in [0, 1, 5, 0x80, u64::MAX] {
+          let bits: Vec<bool> = (0..width).map(|i|value >> i & 1 == 1).collect();
+          let text = format.format(&bits);
+          let text = if format == Format::Bin { format!("0b{}", text) } else { text };
+          assert_eq!(parse_value(&text, width), Ok(bits), "{} as {}", text, format);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn formats() {
+    let bits = parse_value("-3", 4).unwrap();
+    assert_eq!(bits, [true, false, true, true]);
+    assert_eq!(Format::Bin.format(&bits), "1101");
+    assert_eq!(Format::Hex.format(&bits), "0xD");
+    assert_eq!(Format::Unsigned.format(&bits), "13");
+    assert_eq!(Format::Signed.format(&bits), "-3");
+    assert_eq!(Format::Hex.format(&parse_value("0B101", 9).unwrap()), "0x005");
+    assert_eq!(Format::Signed.format(&parse_value("-8", 4).unwrap()), "-8");
+  }
+
+  #[test]
+  fn parse_errors() {
+    assert_eq!(Format::parse("oct"), Err("Unknown format: oct".to_owned()));
+    assert_eq!(parse_value("0xg", 8), Err("Not a number: 0xg".to_owned()));
+    assert_eq!(parse_value("", 8), Err("Not a number: ".to_owned()));
+    assert_eq!(parse_value("16", 4), Err("16 does not fit in 4 bits".to_owned()));
+    assert_eq!(parse_value("-9", 4), Err("-9 does not fit in 4 bits".to_owned()));
+  }
+
+  #[test]
+  fn ranges() {
+    assert_eq!(expand("o0..o3"), Ok(vec!["o0".to_owned(), "o1".to_owned(), "o2".to_owned(), "o3".to_owned()]));
+    assert_eq!(expand("o2..o1"), Ok(vec!["o2".to_owned(), "o1".to_owned()]));
+    assert_eq!(expand("carry"), Ok(vec!["carry".to_owned()]));
+    assert_eq!(expand("a0..b3"), Err("Invalid range: a0..b3".to_owned()));
+    assert_eq!(expand("a..a3"), Err("Invalid range: a..a3".to_owned()));
+  }
+
+  #[test]
+  fn groups() {
+    let mut circuit = circuit();
+    let mut groups = Groups::default();
+    groups.add("a", &["a0..a3"], Format::Signed, &circuit).unwrap();
+    groups.add("o", &["o0", "o1..o2"], Format::Hex, &circuit).unwrap();
+    let inputs = groups.assign(&circuit, &["a=-2", "c=1"]).unwrap();
+    assert_eq!(inputs, [false, true, true, true, true]);
+    circuit.set_input(inputs).unwrap();
+    let mut state = circuit.new_state();
+    for _ in 0..2 {
+      circuit.update(&mut state);
+    }
+    assert_eq!(groups.format_output(&circuit, &state), "o=0x6 o3=1");
+    assert_eq!(groups.list[0].to_string(), "a: 4 input bits as signed");
+    assert!(groups.remove("o"));
+    assert!(!groups.remove("o"));
+  }
+
+  #[test]
+  fn group_errors() {
+    let circuit = circuit();
+    let mut groups = Groups::default();
+    assert_eq!(groups.add("g", &["x"], Format::Bin, &circuit), Err("No port named x".to_owned()));
+    assert_eq!(groups.add("g", &["a0", "o0"], Format::Bin, &circuit), Err("g mixes inputs and outputs".to_owned()));
+    assert_eq!(groups.add("g", &[], Format::Bin, &circuit), Err("A group needs at least one port".to_owned()));
+    assert_eq!(groups.get_mut("g").err(), Some("No group named g".to_owned()));
+    assert_eq!(groups.assign(&circuit, &["a0"]), Err("Expected <name>=<value>, recieved a0".to_owned()));
+    assert_eq!(groups.assign(&circuit, &["o0=1"]), Err("No input named o0".to_owned()));
+    assert_eq!(groups.assign(&circuit, &["c=2"]), Err("2 does not fit in 1 bits".to_owned()));
+  }
+}
@@ -0,0 +1,118 @@
+use std::collections::{ HashMap, VecDeque };
+use crate::base::WholeNewState;
+use crate::circuit::Circuit;
+
+struct Snapshot {
+  state: WholeNewState,
+  inputs: Vec<bool>,
+}
+
+impl Snapshot {
+  fn take(circuit: &Circuit, state: &WholeNewState) -> Self {
+    Snapshot { state: state.clone(), inputs: circuit.get_input() }
+  }
+  fn size(&self) -> usize {
+    let memories: usize = self.state.memories.iter().map(|m|m.len() * 8).sum();
+    std::mem::size_of::<Self>() + self.state.components.len() * 2 + self.inputs.len() + memories
+  }
+  fn restore(&self, circuit: &mut Circuit, state: &mut WholeNewState) {
+    *state = self.state.clone();
+    circuit.set_input(self.inputs.clone()).unwrap();
+  }
+}
+
+/// Past states of a circuit, with the oldest dropped once they no longer fit in the memory budget.
+/// Checkpoints count toward the budget too, but are only dropped by replacing them.
+pub struct History {
+  snapshots: VecDeque<Snapshot>,
+  checkpoints: HashMap<String, Snapshot>,
+  budget: usize,
+  used: usize,
+}
+
+impl History {
+  pub fn new(budget: usize) -> Self {
+    History { snapshots: VecDeque::new(), checkpoints: HashMap::new(), budget, used: 0 }
+  }
+  pub fn len(&self) -> usize {
+    self.snapshots.len()
+  }
+  pub fn is_empty(&self) -> bool {
+    self.snapshots.is_empty()
+  }
+  pub fn checkpoint_count(&self) -> usize {
+    self.checkpoints.len()
+  }
+  pub fn used(&self) -> usize {
+    self.used
+  }
+  fn checkpoint_size(&self) -> usize {
+    self.checkpoints.values().map(Snapshot::size).sum()
+  }
+  pub fn budget(&self) -> usize {
+    self.budget
+  }
+  pub fn set_budget(&mut self, budget: usize) -> Result<(), String> {
+    let checkpoints = self.checkpoint_size();
+    if checkpoints > budget {
+      return Err(format!("The checkpoints alone use {} bytes", checkpoints));
+    }
+    self.budget = budget;
+    self.shrink();
+    Ok(())
+  }
+  fn shrink(&mut self) {
+    while self.used > self.budget {
+      match self.snapshots.pop_front() {
+        Some(snapshot) => self.used -= snapshot.size(),
+        None => break,
+      }
+    }
+  }
+  /// Records the current state, so it can be returned to with `back`.
+  pub fn record(&mut self, circuit: &Circuit, state: &WholeNewState) {
+    let snapshot = Snapshot::take(circuit, state);
+    self.used += snapshot.size();
+    self.snapshots.push_back(snapshot);
+    self.shrink();
+  }
+  /// Returns to the state `steps` records ago.
+  pub fn back(&mut self, steps: usize, circuit: &mut Circuit, state: &mut WholeNewState) -> Result<(), String> {
+    if steps == 0 {
+      return Ok(());
+    }
+    if steps > self.snapshots.len() {
+      return Err(format!("Only {} states are kept in the history", self.snapshots.len()));
+    }
+    let keep = self.snapshots.len() - steps;
+    for snapshot in self.snapshots.drain(keep + 1..) {
+      self.used -= snapshot.size();
+    }
+    let snapshot = self.snapshots.pop_back().unwrap();
+    self.used -= snapshot.size();
+    snapshot.restore(circuit, state);
+    Ok(())
+  }
+  /// Remembers the current state under a name, replacing any checkpoint of that name.
+  pub fn checkpoint(&mut self, name: String, circuit: &Circuit, state: &WholeNewState) -> Result<(), String> {
+    let snapshot = Snapshot::take(circuit, state);
+    let replaced = self.checkpoints.get(&name).map_or(0, Snapshot::size);
+    let checkpoints = self.checkpoint_size() - replaced + snapshot.size();
+    if checkpoints > self.budget {
+      return Err(format!("The checkpoints would use {} bytes, more than the history budget of {}", checkpoints, self.budget));
+    }
+    self.used = self.used - replaced + snapshot.size();
+    self.checkpoints.insert(name, snapshot);
+    self.shrink();
+    Ok(())
+  }
+  /// Returns to a checkpoint, recording the current state first so the restore can be undone with `back`.
+  pub fn restore(&mut self, name: &str, circuit: &mut Circuit, state: &mut WholeNewState) -> Result<(), String> {
+    if !self.checkpoints.contains_key(name) {
+      return Err(format!("Unknown checkpoint: {}", name));
+    }
+    self.record(circuit, state);
+    self.checkpoints[name].restore(circuit, state);
+    Ok(())
+  }
+}
@@ -0,0 +1,192 @@
+use pest_derive::Parser;
+use pest::Parser;
+use std::fmt::{ self, Display, Formatter, Write };
+type Pair<'i> = pest::iterators::Pair<'i, Rule>;
+
+#[derive(Parser)]
+#[grammar = "json.pest"]
+struct JsonParser;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+  Null,
+  Bool(bool),
+  Number(f64),
+  String(String),
+  Array(Vec<Json>),
+  Object(Vec<(String, Json)>),
+}
+
+fn unescape(s: &str) -> String {
+  let mut out = String::with_capacity(s.len());
+  let mut chars = s.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' {
+      out.push(c);
+      continue;
+    }
+    match chars.next() {
+      Some('b') => out.push('\u{8}'),
+      Some('f') => out.push('\u{c}'),
+      Some('n') => out.push('\n'),
+      Some('r') => out.push('\r'),
+      Some('t') => out.push('\t'),
+      Some('u') => {
+        let code: String = chars.by_ref().take(4).collect();
+        out.push(u32::from_str_radix(&code, 16).ok().and_then(std::char::from_u32).unwrap_or('\u{fffd}'));
+      },
+      Some(c) => out.push(c),
+      None => {},
+    }
+  }
+  out
+}
+
+impl Json {
+  pub fn parse(s: &str) -> Result<Json, String> {
+    let pair = JsonParser::parse(Rule::json, s).map_err(|e|format!("{}", e))?.next().unwrap();
+    Ok(Json::from_pair(pair))
+  }
+  fn from_pair(pair: Pair) -> Json {
+    match pair.as_rule() {
+      Rule::object => Json::Object(pair.into_inner().map(|pair|{
+        let mut pairs = pair.into_inner();
+        let key = unescape(pairs.next().unwrap().into_inner().next().unwrap().as_str());
+        (key, Json::from_pair(pairs.next().unwrap()))
+      }).collect()),
+      Rule::array => Json::Array(pair.into_inner().map(Json::from_pair).collect()),
+      Rule::string => Json::String(unescape(pair.into_inner().next().unwrap().as_str())),
+      Rule::number => Json::Number(pair.as_str().parse().unwrap()),
+      Rule::boolean => Json::Bool(pair.as_str() == "true"),
+      Rule::null => Json::Null,
+      r => unreachable!("{:?}", r),
+    }
+  }
+  pub fn object<K: Into<String>>(pairs: Vec<(K, Json)>) -> Json {
+    Json::Object(pairs.into_iter().map(|(k, v)|(k.into(), v)).collect())
+  }
+  pub fn get(&self, key: &str) -> Option<&Json> {
+    match self {
+      Json::Object(pairs) => pairs.iter().find(|(k, _)|k == key).map(|(_, v)|v),
+      _ => None,
+    }
+  }
+  /// Looks up a required field, naming it in the error.
+  pub fn field(&self, key: &str) -> Result<&Json, String> {
+    self.get(key).ok_or_else(||format!("Missing field: {}", key))
+  }
+  pub fn as_str(&self) -> Option<&str> {
+    match self {
+      Json::String(s) => Some(s),
+      _ => None,
+    }
+  }
+  pub fn as_usize(&self) -> Option<usize> {
+    match *self {
+      Json::Number(n) if n >= 0.0 && n.fract() == 0.0 => Some(n as usize),
+      _ => None,
+    }
+  }
+  pub fn as_bool(&self) -> Option<bool> {
+    match *self {
+      Json::Bool(b) => Some(b),
+      _ => None,
+    }
+  }
+  pub fn as_array(&self) -> Option<&[Json]> {
+    match self {
+      Json::Array(v) => Some(v),
+      _ => None,
+    }
+  }
+  pub fn as_object(&self) -> Option<&[(String, Json)]> {
+    match self {
+      Json::Object(v) => Some(v),
+      _ => None,
+    }
+  }
+}
+
+impl From<bool> for Json {
+  fn from(b: bool) -> Self {
+    Json::Bool(b)
+  }
+}
+
+impl From<usize> for Json {
+  fn from(n: usize) -> Self {
+    Json::Number(n as f64)
+  }
+}
+
+impl From<&str> for Json {
+  fn from(s: &str) -> Self {
+    Json::String(s.to_owned())
+  }
+}
+
+impl From<String> for Json {
+  fn from(s: String) -> Self {
+    Json::String(s)
+  }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+  fn from(v: Vec<T>) -> Self {
+    Json::Array(v.into_iter().map(Into::into).collect())
+  }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+  fn from(v: Option<T>) -> Self {
+    v.map_or(Json::Null, Into::into)
+  }
+}
+
+fn write_string(f: &mut Formatter, s: &str) -> fmt::Result {
+  f.write_char('"')?;
+  for c in s.chars() {
+    match c {
+      '"' => f.write_str("\\\"")?,
+      '\\' => f.write_str("\\\\")?,
+      '\n' => f.write_str("\\n")?,
+      '\r' => f.write_str("\\r")?,
+      '\t' => f.write_str("\\t")?,
+      c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+      c => f.write_char(c)?,
+    }
+  }
+  f.write_char('"')
+}
+
+impl Display for Json {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match self {
+      Json::Null => f.write_str("null"),
+      Json::Bool(b) => write!(f, "{}", b),
+      Json::Number(n) => write!(f, "{}", n),
+      Json::String(s) => write_string(f, s),
+      Json::Array(v) => {
+        f.write_char('[')?;
+        for (i, item) in v.iter().enumerate() {
+          if i > 0 {
+            f.write_char(',')?;
+          }
+          write!(f, "{}", item)?;
+        }
+        f.write_char(']')
+      },
+      Json::Object(v) => {
+        f.write_char('{')?;
+        for (i, (key, item)) in v.iter().enumerate() {
+          if i > 0 {
+            f.write_char(',')?;
+          }
+          write_string(f, key)?;
+          write!(f, ":{}", item)?;
+        }
+        f.write_char('}')
+      },
+    }
+  }
+}
@@ -1,3 +1,15 @@
 pub mod base;
+pub mod blif;
+pub mod cache;
 pub mod circuit;
+pub mod dot;
+pub mod explore;
+pub mod format;
+pub mod history;
+pub mod json;
+pub mod memory;
+pub mod netlist;
 pub mod slot_vec;
+pub mod state;
+pub mod watch;
+pub mod yosys;
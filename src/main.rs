@@ -1,9 +1,22 @@
-use std::io::{stdin, BufRead};
+use std::io::{stdout, BufWriter};
+use std::fs::File;
 use std::convert::TryInto;
-use circuit_sim::base::WholeNewState;
+use std::path::Path;
+use circuit_sim::base::{ Component, WholeNewState };
 use circuit_sim::circuit::*;
+use circuit_sim::json::Json;
+use circuit_sim::cache::source_hash;
+use circuit_sim::history::History;
+use circuit_sim::watch::Breakpoints;
+use circuit_sim::format::{ Format, Groups };
+use circuit_sim::memory::{ read_words, write_hex };
 mod env;
 mod ast;
+mod script;
+mod repl;
+use ast::mir::{ self, Hierarchy };
+use rustyline::Editor;
+use rustyline::error::ReadlineError;
 
 fn parse_bool(s: char) -> Result<bool, String> {
   match s {
@@ -12,28 +25,318 @@ fn parse_bool(s: char) -> Result<bool, String> {
     s => Err(format!("Undefined value: {}", s))
   }
 }
-fn run_command(circuit: &mut Circuit, state: &mut WholeNewState, cmd: &str, args: &[&str]) -> Result<bool, String> {
+fn parse_size(s: &str) -> Result<usize, String> {
+  let (num, unit) = match s.char_indices().find(|(_, c)|!c.is_ascii_digit()) {
+    Some((i, _)) => s.split_at(i),
+    None => (s, ""),
+  };
+  let unit = match unit.to_ascii_uppercase().as_str() {
+    "" | "B" => 1,
+    "K" | "KB" => 1 << 10,
+    "M" | "MB" => 1 << 20,
+    "G" | "GB" => 1 << 30,
+    _ => return Err(format!("Unknown size unit: {}", unit)),
+  };
+  num.parse::<usize>().map(|n|n * unit).map_err(|_|format!("Not a size: {}", s))
+}
+fn parse(src: &str, path: &Path, nand_xor: bool) -> Result<ast::Program, String> {
+  let mut program = ast::parse(src, path)?;
+  if nand_xor {
+    program.funcs.iter_mut().zip(&program.signs).for_each(|(func, sign)|func.expand_xor(sign));
+  }
+  Ok(program)
+}
+struct Session {
+  funcs: Vec<mir::Func>,
+  /// The signature of each function, by id.
+  signs: Vec<mir::FuncSign>,
+  root: Option<usize>,
+  circuit: Circuit,
+  state: WholeNewState,
+  history: History,
+  breakpoints: Breakpoints,
+  /// Inputs toggled during `run`, as input index and period.
+  clocks: Vec<(usize, usize)>,
+  /// The scripts currently being run, innermost last.
+  scripts: Vec<String>,
+  groups: Groups,
+}
+impl Session {
+  fn root(&self) -> Result<usize, String> {
+    self.root.ok_or_else(||"The circuit was not built from .cir source".to_owned())
+  }
+  fn memory_index(&self, index: &str) -> Result<usize, String> {
+    index.parse().ok().filter(|i|*i < self.circuit.memories().len()).ok_or_else(||format!("No memory {}", index))
+  }
+  fn input_index(&self, name: &str) -> Result<usize, String> {
+    self.circuit.find_port(name).filter(|(input, _)|*input).map(|(_, i)|i)
+      .or_else(||name.parse().ok().filter(|i|*i < self.circuit.input_count()))
+      .ok_or_else(||format!("No input named {}", name))
+  }
+  fn print_output(&self) {
+    if self.groups.list.iter().any(|g|!g.input) {
+      println!("{}", self.groups.format_output(&self.circuit, &self.state));
+    } else {
+      self.circuit.print_output(&self.state);
+    }
+  }
+  /// Drives the clocked inputs for the next step.
+  fn tick_clocks(&mut self) -> Result<(), String> {
+    if self.clocks.is_empty() {
+      return Ok(());
+    }
+    let mut input = self.circuit.get_input();
+    for (i, period) in &self.clocks {
+      input[*i] = Component::clock_value(*period, *period / 2, 0, self.state.steps);
+    }
+    self.circuit.set_input(input)
+  }
+}
+fn run_script(session: &mut Session, path: &str) -> Result<bool, String> {
+  if session.scripts.iter().any(|p|p == path) {
+    return Err(format!("{} sources itself", path));
+  }
+  let src = std::fs::read_to_string(path).map_err(|e|format!("{}: {}", path, e))?;
+  let commands = script::parse(&src).map_err(|e|format!("{}: {}", path, e))?;
+  session.scripts.push(path.to_owned());
+  let result = run_commands(session, &commands, path);
+  session.scripts.pop();
+  result
+}
+fn run_commands(session: &mut Session, commands: &[script::Command], path: &str) -> Result<bool, String> {
+  for command in commands {
+    let exit = match command {
+      script::Command::Line(line_no, words) => {
+        let args: Vec<&str> = words[1..].iter().map(String::as_str).collect();
+        run_command(session, &words[0], &args).map_err(|e|format!("{}:{}: {}", path, line_no, e))?
+      },
+      script::Command::Repeat(count, body) => {
+        let mut exit = false;
+        for _ in 0..*count {
+          exit = run_commands(session, body, path)?;
+          if exit {
+            break;
+          }
+        }
+        exit
+      },
+    };
+    if exit {
+      return Ok(true);
+    }
+  }
+  Ok(false)
+}
+fn run_command(session: &mut Session, cmd: &str, args: &[&str]) -> Result<bool, String> {
   match cmd {
     "set" => {
-      let [arg]: [&str; 1] = args.try_into().map_err(|_|format!("Expected 1 argument, recieved {}", args.len()))?;
-      let input = arg.chars().map(parse_bool).collect::<Result<_, String>>()?;
-      circuit.set_input(input)?;
+      let input = match args {
+        [] => return Err("Expected at least 1 argument".to_owned()),
+        [arg] if !arg.contains('=') => arg.chars().map(parse_bool).collect::<Result<_, String>>()?,
+        assignments => session.groups.assign(&session.circuit, assignments)?,
+      };
+      session.history.record(&session.circuit, &session.state);
+      session.circuit.set_input(input)?;
     },
     "run" => {
       let [arg]: [&str; 1] = args.try_into().map_err(|_|format!("Expected 1 argument, recieved {}", args.len()))?;
       let steps = arg.parse().map_err(|_|format!("Not a number: {}", arg))?;
       for _ in 0..steps {
-        circuit.update(state);
-        circuit.print_output(state);
+        session.history.record(&session.circuit, &session.state);
+        session.tick_clocks()?;
+        let hits = session.circuit.update_checked(&mut session.state, &session.breakpoints);
+        session.print_output();
+        if !hits.is_empty() {
+          hits.iter().for_each(|hit|println!("{}", hit));
+          break;
+        }
       }
     },
     "save" => {
       let [path]: [&str; 1] = args.try_into().map_err(|_|format!("Expected 1 argument, recieved {}", args.len()))?;
-      state.save(path).map_err(|e|format!("{}", e))?;
+      session.circuit.save_state(&session.state, path).map_err(|e|format!("{}", e))?;
     },
     "load" => {
+      let (path, by_name) = match args {
+        [path] => (path, false),
+        [path, "names"] => (path, true),
+        [_, arg] => return Err(format!("Unknown option: {}", arg)),
+        _ => return Err(format!("Expected 1 or 2 arguments, recieved {}", args.len())),
+      };
+      session.history.record(&session.circuit, &session.state);
+      if let Some(restored) = session.circuit.load_state(&mut session.state, path, by_name)? {
+        println!("Restored {} values by name", restored.matched);
+        if !restored.missing.is_empty() {
+          println!("No longer in the circuit: {}", restored.missing.join(", "));
+        }
+      }
+    },
+    "back" => {
+      let steps = match args {
+        [] => 1,
+        [arg] => arg.parse().map_err(|_|format!("Not a number: {}", arg))?,
+        _ => return Err(format!("Expected at most 1 argument, recieved {}", args.len())),
+      };
+      session.history.back(steps, &mut session.circuit, &mut session.state)?;
+      session.print_output();
+    },
+    "checkpoint" => {
+      let [name]: [&str; 1] = args.try_into().map_err(|_|format!("Expected 1 argument, recieved {}", args.len()))?;
+      session.history.checkpoint(name.to_owned(), &session.circuit, &session.state)?;
+    },
+    "restore" => {
+      let [name]: [&str; 1] = args.try_into().map_err(|_|format!("Expected 1 argument, recieved {}", args.len()))?;
+      session.history.restore(name, &mut session.circuit, &mut session.state)?;
+      session.print_output();
+    },
+    "history" => match args {
+      [] => println!("{} states and {} checkpoints kept, using {} of {} bytes", session.history.len(), session.history.checkpoint_count(), session.history.used(), session.history.budget()),
+      [budget] => session.history.set_budget(parse_size(budget)?)?,
+      _ => return Err(format!("Expected at most 1 argument, recieved {}", args.len())),
+    },
+    "break" => match args {
+      [] => session.breakpoints.list.iter().enumerate().for_each(|(i, b)|println!("{}: {}", i, b)),
+      ["when", expr @ ..] => session.breakpoints.add_condition(&expr.join(" "), &session.circuit, false)?,
+      ["becomes", expr @ ..] => session.breakpoints.add_condition(&expr.join(" "), &session.circuit, true)?,
+      _ => return Err("Expected break [when|becomes] <condition>".to_owned()),
+    },
+    "watch" => {
+      let [name]: [&str; 1] = args.try_into().map_err(|_|format!("Expected 1 argument, recieved {}", args.len()))?;
+      session.breakpoints.add_watch(name, &session.circuit)?;
+    },
+    "clear" => match args {
+      [] => session.breakpoints.list.clear(),
+      [arg] => {
+        let i: usize = arg.parse().map_err(|_|format!("Not a number: {}", arg))?;
+        if i >= session.breakpoints.list.len() {
+          return Err(format!("No breakpoint {}", i));
+        }
+        session.breakpoints.list.remove(i);
+      },
+      _ => return Err(format!("Expected at most 1 argument, recieved {}", args.len())),
+    },
+    "group" => match args {
+      [] => session.groups.list.iter().for_each(|g|println!("{}", g)),
+      [_] => return Err("Expected group <name> <ports> [format]".to_owned()),
+      [name, ports @ ..] => {
+        let (ports, format) = match ports.split_last() {
+          Some((last, rest)) if !rest.is_empty() && Format::parse(last).is_ok() => (rest, Format::parse(last)?),
+          _ => (ports, Format::Bin),
+        };
+        session.groups.add(name, ports, format, &session.circuit)?;
+      },
+    },
+    "ungroup" => {
+      let [name]: [&str; 1] = args.try_into().map_err(|_|format!("Expected 1 argument, recieved {}", args.len()))?;
+      if !session.groups.remove(name) {
+        return Err(format!("No group named {}", name));
+      }
+    },
+    "format" => {
+      let [name, format]: [&str; 2] = args.try_into().map_err(|_|format!("Expected 2 arguments, recieved {}", args.len()))?;
+      session.groups.get_mut(name)?.format = Format::parse(format)?;
+    },
+    "clock" => match args {
+      [] => for (i, period) in &session.clocks {
+        let name = session.circuit.port_name(session.circuit.inputs()[*i]).map_or_else(||i.to_string(), str::to_owned);
+        println!("{}: period {}", name, period);
+      },
+      [name, "off"] => {
+        let i = session.input_index(name)?;
+        session.clocks.retain(|(j, _)|*j != i);
+      },
+      [name, period] => {
+        let i = session.input_index(name)?;
+        let period = period.parse().ok().filter(|p|*p >= 2).ok_or_else(||format!("Not a period of at least 2: {}", period))?;
+        session.clocks.retain(|(j, _)|*j != i);
+        session.clocks.push((i, period));
+      },
+      _ => return Err("Expected clock <input> <period|off>".to_owned()),
+    },
+    "mem" => match args {
+      [] => for (i, memory) in session.circuit.memories().iter().enumerate() {
+        // The instance the memory belongs to is the one owning the wires it drives.
+        let wire = session.circuit.components().iter().position(|(c, _)|matches!(c, Component::Ram(m, ..) if *m == i));
+        let owner = wire.map_or(0, |wire|session.circuit.owner(wire));
+        let writes = if memory.clock.is_some() { "on rising clock edges" } else { "while enabled" };
+        println!("{}: {} words of {} bits in {}, written {}", i, memory.size(), memory.data.len(), session.circuit.instances()[owner].label, writes);
+      },
+      ["dump", index, path] => {
+        let i = session.memory_index(index)?;
+        let hex = write_hex(&session.state.memories[i], session.circuit.memories()[i].data.len());
+        std::fs::write(path, hex).map_err(|e|format!("{}: {}", path, e))?;
+      },
+      ["load", index, path] => {
+        let i = session.memory_index(index)?;
+        let (size, width) = (session.circuit.memories()[i].size(), session.circuit.memories()[i].data.len());
+        let words = read_words(Path::new(path))?;
+        if words.len() > size {
+          return Err(format!("{} has {} words, but memory {} holds {}", path, words.len(), i, size));
+        }
+        if let Some(word) = words.iter().find(|w|width < 64 && **w >> width != 0) {
+          return Err(format!("{:#x} does not fit in {} bits", word, width));
+        }
+        session.history.record(&session.circuit, &session.state);
+        session.state.memories[i][..words.len()].copy_from_slice(&words);
+      },
+      _ => return Err("Expected mem [dump|load <memory> <path>]".to_owned()),
+    },
+    "explore" => {
+      let (limit, path) = match args {
+        [] => (1024, None),
+        [limit] => (limit.parse().map_err(|_|format!("Not a number: {}", limit))?, None),
+        [limit, path] => (limit.parse().map_err(|_|format!("Not a number: {}", limit))?, Some(path)),
+        _ => return Err(format!("Expected at most 2 arguments, recieved {}", args.len())),
+      };
+      let graph = session.circuit.explore(limit)?;
+      match path {
+        None => print!("{}", graph),
+        Some(path) => graph.write_dot(File::create(path).map_err(|e|format!("{}", e))?).map_err(|e|format!("{}", e))?,
+      }
+    },
+    "dot" => {
+      let (path, cluster) = match args {
+        [path] => (path, false),
+        [path, "cluster"] => (path, true),
+        [_, arg] => return Err(format!("Unknown option: {}", arg)),
+        _ => return Err(format!("Expected 1 or 2 arguments, recieved {}", args.len())),
+      };
+      session.circuit.write_dot(File::create(path).map_err(|e|format!("{}", e))?, cluster).map_err(|e|format!("{}", e))?;
+    },
+    "tree" => {
+      let []: [&str; 0] = args.try_into().map_err(|_|format!("Expected 0 arguments, recieved {}", args.len()))?;
+      let root = session.root()?;
+      Hierarchy::new(&session.funcs, &session.signs, root).write_tree(stdout()).map_err(|e|format!("{}", e))?;
+    },
+    "blocks" => {
+      let [path]: [&str; 1] = args.try_into().map_err(|_|format!("Expected 1 argument, recieved {}", args.len()))?;
+      let root = session.root()?;
+      Hierarchy::new(&session.funcs, &session.signs, root).write_dot(File::create(path).map_err(|e|format!("{}", e))?).map_err(|e|format!("{}", e))?;
+    },
+    "verilog" => {
+      let [path]: [&str; 1] = args.try_into().map_err(|_|format!("Expected 1 argument, recieved {}", args.len()))?;
+      let root = session.root()?;
+      mir::write_verilog(&session.funcs, &session.signs, root, File::create(path).map_err(|e|format!("{}", e))?).map_err(|e|format!("{}", e))?;
+    },
+    "blif" => {
+      let (path, delay) = match args {
+        [path] => (path, false),
+        [path, "delay"] => (path, true),
+        [_, arg] => return Err(format!("Unknown option: {}", arg)),
+        _ => return Err(format!("Expected 1 or 2 arguments, recieved {}", args.len())),
+      };
+      session.circuit.write_blif(File::create(path).map_err(|e|format!("{}", e))?, delay).map_err(|e|format!("{}", e))?;
+    },
+    "echo" => println!("{}", args.join(" ")),
+    "source" => {
       let [path]: [&str; 1] = args.try_into().map_err(|_|format!("Expected 1 argument, recieved {}", args.len()))?;
-      state.load(path).map_err(|e|format!("{}", e))?;
+      return run_script(session, path);
+    },
+    "repeat" => return Err("repeat blocks are only supported in scripts".to_owned()),
+    "help" => match args {
+      [] => repl::help(None)?,
+      [cmd] => repl::help(Some(cmd))?,
+      _ => return Err(format!("Expected at most 1 argument, recieved {}", args.len())),
     },
     "exit" => return Ok(true),
     cmd => return Err(format!("Unknown command: {}", cmd)),
@@ -41,24 +344,117 @@ fn run_command(circuit: &mut Circuit, state: &mut WholeNewState, cmd: &str, args
   Ok(false)
 }
 fn main() {
+  let mut positional = vec![];
+  let mut json_out = None;
+  let mut use_cache = true;
+  let mut script = None;
+  let mut nand_xor = false;
   let mut args = std::env::args().skip(1);
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--json" => json_out = Some(args.next().expect("--json expects a path")),
+      "--no-cache" => use_cache = false,
+      "--nand-xor" => nand_xor = true,
+      "--script" => script = Some(args.next().expect("--script expects a path")),
+      _ => positional.push(arg),
+    }
+  }
+  let mut args = positional.into_iter();
   let path = args.next().unwrap();
-  let func_name = args.next().unwrap();
-  let (funcs, env) = ast::parse(&std::fs::read_to_string(path).unwrap()).unwrap_or_else(|e|panic!(e));
-  //println!("{:#?}", env);
-  let sign = &env[&func_name];
-  let mut circuit = funcs[sign.id].build_circuit(&funcs, sign);
-  //println!("{:#?}", circuit);
-  let mut state = circuit.new_state();
-  for line in stdin().lock().lines().map(|l|l.unwrap()) {
-    let args: Vec<&str> = line.split_whitespace().collect();
+  let src = std::fs::read_to_string(&path).unwrap();
+  let (funcs, signs, root, mut circuit) = if path.ends_with(".blif") {
+    let circuit = Circuit::read_blif(&src).unwrap_or_else(|e|panic!("{}", e));
+    (vec![], vec![], None, circuit)
+  } else if path.ends_with(".json") {
+    let json = Json::parse(&src).unwrap_or_else(|e|panic!("{}", e));
+    if mir::is_dump(&json) {
+      let (funcs, signs, root, circuit) = mir::read_dump(&json).unwrap_or_else(|e|panic!("{}", e));
+      (funcs, signs, Some(root), circuit)
+    } else if json.get("components").is_some() {
+      let circuit = Circuit::from_json(&json).unwrap_or_else(|e|panic!("{}", e));
+      (vec![], vec![], None, circuit)
+    } else {
+      let circuit = Circuit::from_yosys_json(&json, args.next().as_deref()).unwrap_or_else(|e|panic!("{}", e));
+      (vec![], vec![], None, circuit)
+    }
+  } else {
+    let func_name = args.next().unwrap();
+    let ast::Program { funcs, signs, env, contents } = parse(&src, Path::new(&path), nand_xor).unwrap_or_else(|e|panic!("{}", e));
+    //println!("{:#?}", env);
+    let sign = &env[&func_name];
+    let root = sign.id;
+    // The circuit depends on the source and every file parsing it read, so editing any of them invalidates the cache.
+    let mut key = format!("{}\0{}\0{}\0{}\0", env!("CARGO_PKG_VERSION"), func_name, nand_xor, src).into_bytes();
+    key.extend(contents);
+    let hash = source_hash(&key);
+    let cache_path = format!("{}.{}.cache", path, func_name);
+    let cached = if use_cache {
+      std::fs::read(&cache_path).ok().and_then(|data|Circuit::read_binary(&data, hash).ok())
+    } else {
+      None
+    };
+    let circuit = match cached {
+      Some(circuit) => circuit,
+      None => {
+        let circuit = funcs[root].build_circuit(&funcs, sign);
+        //println!("{:#?}", circuit);
+        if use_cache {
+          if let Err(e) = File::create(&cache_path).and_then(|file|circuit.write_binary(BufWriter::new(file), hash)) {
+            println!("Unable to write {}: {}", cache_path, e);
+          }
+        }
+        circuit
+      },
+    };
+    (funcs, signs, Some(root), circuit)
+  };
+  let state = circuit.new_state();
+  let history = History::new(64 << 20);
+  let mut session = Session { funcs, signs, root, circuit, state, history, breakpoints: Breakpoints::default(), clocks: vec![], scripts: vec![], groups: Groups::default() };
+  if let Some(json_out) = json_out {
+    let json = match session.root() {
+      Ok(root) => mir::write_dump(&session.funcs, &session.signs, root, &session.circuit),
+      Err(_) => session.circuit.to_json(),
+    };
+    std::fs::write(json_out, json.to_string()).unwrap();
+  }
+  if let Some(script) = script {
+    if let Err(err) = run_script(&mut session, &script) {
+      println!("{}", err);
+      std::process::exit(1);
+    }
+    return;
+  }
+  let mut names: Vec<_> = session.circuit.wire_names().into_iter().map(|(name, _)|name).collect();
+  names.sort_unstable();
+  names.dedup();
+  let mut editor = Editor::new();
+  editor.set_helper(Some(repl::ReplHelper::new(names)));
+  let history_path = std::env::var_os("HOME").map(|home|std::path::Path::new(&home).join(".circuit-sim-history"));
+  if let Some(path) = &history_path {
+    let _ = editor.load_history(path);
+  }
+  loop {
+    let line = match editor.readline("> ") {
+      Ok(line) => line,
+      Err(ReadlineError::Interrupted) => continue,
+      Err(ReadlineError::Eof) => break,
+      Err(err) => panic!("{}", err),
+    };
+    let args = script::words(&line);
     let (cmd, args) = match args.split_first() {
       Some(cmd) => cmd,
       None => continue,
     };
-    match run_command(&mut circuit, &mut state, cmd, &args) {
-      Ok(b) => if b { return },
+    editor.add_history_entry(line.trim());
+    match run_command(&mut session, cmd, args) {
+      Ok(b) => if b { break },
       Err(err) => println!("{}", err),
     }
   }
+  if let Some(path) = &history_path {
+    if let Err(e) = editor.save_history(path) {
+      println!("Unable to write {}: {}", path.display(), e);
+    }
+  }
 }
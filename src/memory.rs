@@ -0,0 +1,31 @@
+//! Files holding the contents of ROMs and RAMs.
+//!
+//! Files ending in `.bin` are raw bytes, one word each. Any other file is read as hex words
+//! separated by whitespace, where `//` and `#` start comments that run to the end of the line.
+use std::path::Path;
+
+pub fn read_words(path: &Path) -> Result<Vec<u64>, String> {
+  let error = |e: std::io::Error|format!("Unable to read {}: {}", path.display(), e);
+  if path.extension().is_some_and(|ext|ext == "bin") {
+    return Ok(std::fs::read(path).map_err(error)?.into_iter().map(u64::from).collect());
+  }
+  parse_hex(&std::fs::read_to_string(path).map_err(error)?).map_err(|e|format!("{}: {}", path.display(), e))
+}
+
+/// Writes one hex word per line, padded to the digits of `width` bits.
+pub fn write_hex(words: &[u64], width: usize) -> String {
+  words.iter().map(|w|format!("{:01$x}\n", w, width.div_ceil(4))).collect()
+}
+
+/// Parses whitespace separated hex words, with an optional `0x` prefix.
+pub fn parse_hex(src: &str) -> Result<Vec<u64>, String> {
+  let mut words = vec![];
+  for (line_no, line) in src.lines().enumerate() {
+    let line = line.split("//").next().unwrap().split('#').next().unwrap();
+    for word in line.split_whitespace() {
+      let digits = word.strip_prefix("0x").unwrap_or(word);
+      words.push(u64::from_str_radix(digits, 16).map_err(|_|format!("line {}: Not a hex word: {}", line_no + 1, word))?);
+    }
+  }
+  Ok(words)
+}
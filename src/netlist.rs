@@ -0,0 +1,326 @@
+//! The JSON netlist format.
+//!
+//! A netlist is an object with the fields
+//! - `name`: the name of the top level function.
+//! - `components`: one object per wire, indexed by wire number, with a `type` of
+//!   `source`, `buffer`, `not`, `or`, `and`, `nor`, `nand`, `xor`, `xnor`, `mux`, `decoder`, `encoder`,
+//!   `lut`, `ram`, `bus` or `clock`, and an `init` value.
+//!   Sources carry their `value`, gates the wires they read in `inputs`, with muxes also listing
+//!   their `select` wires, decoders their `select` wires and the `value` they match, encoders their
+//!   `inputs` and the output `bit`, lookup tables their `inputs` and the output for every value
+//!   of them as a string of `0`s and `1`s in `table`, RAM bits the index of their `memory`, their `address`
+//!   wires and their `bit`,
+//!   buses a list of `[high, low]` wire pairs in `drivers` and their `mode` of `float`, `pullup`,
+//!   `pulldown` or `open_drain`, which may be left out for `float`, and clocks their `period`,
+//!   the number of ticks they are `high` and their `phase`.
+//! - `memories`: the RAMs, each with its `address`, `data`, `write` and `clock` (null if none) wires
+//!   and the hex strings of its initial `words`. Netlists without RAMs may leave it out.
+//! - `inputs` and `outputs`: the wires of the ports, in order.
+//! - `instances`: the function calls the netlist was flattened from, each with `func`, `label`,
+//!   the index of its `parent` (null for the top level) and its wire `names` as `[name, wire]` pairs.
+//! - `owners`: the instance each component was placed by, indexed by wire number.
+use crate::base::{ check_memories, BusMode, Component, Data, Memory, WholeNew };
+use crate::circuit::{ Circuit, Instance };
+use crate::json::Json;
+
+fn component_to_json(component: &Component, init: Data) -> Json {
+  let (kind, field, value): (&str, &str, Json) = match component {
+    Component::Source(value) => ("source", "value", (*value).into()),
+    Component::Buffer(a) => ("buffer", "inputs", vec![*a].into()),
+    Component::Inverter(a) => ("not", "inputs", vec![*a].into()),
+    Component::Or(inputs) => ("or", "inputs", inputs.to_vec().into()),
+    Component::And(inputs) => ("and", "inputs", inputs.to_vec().into()),
+    Component::Nor(inputs) => ("nor", "inputs", inputs.to_vec().into()),
+    Component::Nand(inputs) => ("nand", "inputs", inputs.to_vec().into()),
+    Component::Xor(a, b) => ("xor", "inputs", vec![*a, *b].into()),
+    Component::Xnor(a, b) => ("xnor", "inputs", vec![*a, *b].into()),
+    Component::Bus(mode, drivers) => return Json::object(vec![
+      ("type", "bus".into()), ("mode", mode.name().into()),
+      ("drivers", Json::Array(drivers.iter().map(|(h, l)|vec![*h, *l].into()).collect())), ("init", init.into()),
+    ]),
+    Component::Clock { period, high, phase } => return clock_to_json(*period, *high, *phase, init),
+    Component::Mux(data, select) => return Json::object(vec![
+      ("type", "mux".into()), ("inputs", data.to_vec().into()), ("select", select.to_vec().into()), ("init", init.into()),
+    ]),
+    Component::Decoder(select, value) => return Json::object(vec![
+      ("type", "decoder".into()), ("select", select.to_vec().into()), ("value", (*value).into()), ("init", init.into()),
+    ]),
+    Component::Encoder(inputs, bit) => return Json::object(vec![
+      ("type", "encoder".into()), ("inputs", inputs.to_vec().into()), ("bit", (*bit).into()), ("init", init.into()),
+    ]),
+    Component::Lut(inputs, table) => return Json::object(vec![
+      ("type", "lut".into()), ("inputs", inputs.to_vec().into()),
+      ("table", table.iter().map(|b|if *b { '1' } else { '0' }).collect::<String>().as_str().into()), ("init", init.into()),
+    ]),
+    Component::Ram(memory, address, bit) => return Json::object(vec![
+      ("type", "ram".into()), ("memory", (*memory).into()), ("address", address.to_vec().into()), ("bit", (*bit).into()), ("init", init.into()),
+    ]),
+  };
+  Json::object(vec![("type", kind.into()), (field, value), ("init", init.into())])
+}
+
+fn clock_to_json(period: usize, high: usize, phase: usize, init: Data) -> Json {
+  Json::object(vec![("type", "clock".into()), ("period", period.into()), ("high", high.into()), ("phase", phase.into()), ("init", init.into())])
+}
+
+fn memory_to_json(memory: &Memory) -> Json {
+  Json::object(vec![
+    ("address", memory.address.to_vec().into()),
+    ("data", memory.data.to_vec().into()),
+    ("write", memory.write.into()),
+    ("clock", memory.clock.into()),
+    ("words", Json::Array(memory.init.iter().map(|w|format!("{:#x}", w).into()).collect())),
+  ])
+}
+
+fn memory_from_json(json: &Json, len: usize) -> Result<Memory, String> {
+  let wire = |key: &str|json.field(key)?.as_usize().filter(|w|*w < len).ok_or_else(||format!("{} is not a wire", key));
+  let clock = match json.field("clock")? {
+    Json::Null => None,
+    _ => Some(wire("clock")?),
+  };
+  let init = json.field("words")?.as_array().ok_or("words must be an array")?.iter()
+    .map(|w|w.as_str().and_then(|w|u64::from_str_radix(w.strip_prefix("0x")?, 16).ok()).ok_or_else(||format!("Invalid word: {}", w)))
+    .collect::<Result<_, _>>()?;
+  Ok(Memory {
+    address: wires_field(json, "address", len)?.into(),
+    data: wires_field(json, "data", len)?.into(),
+    write: wire("write")?,
+    clock,
+    init,
+  })
+}
+
+fn bus_mode(json: &Json) -> Result<BusMode, String> {
+  match json.get("mode") {
+    None => Ok(BusMode::Float),
+    Some(mode) => mode.as_str().and_then(BusMode::parse).ok_or_else(||format!("Unknown bus mode: {}", mode)),
+  }
+}
+
+fn wires_field(json: &Json, key: &str, len: usize) -> Result<Vec<usize>, String> {
+  json.field(key)?.as_array().ok_or_else(||format!("{} must be an array", key))?.iter()
+    .map(|w|w.as_usize().filter(|w|*w < len).ok_or_else(||format!("Invalid wire in {}: {}", key, w)))
+    .collect()
+}
+
+fn component_from_json(json: &Json, len: usize) -> Result<(Component, Data), String> {
+  let init = json.field("init")?.as_bool().ok_or("init must be a boolean")?;
+  let kind = json.field("type")?.as_str().ok_or("type must be a string")?;
+  let component = match kind {
+    "clock" => {
+      let number = |key: &str|json.field(key)?.as_usize().ok_or_else(||format!("{} must be a number", key));
+      let (period, high, phase) = (number("period")?, number("high")?, number("phase")?);
+      if period == 0 || high > period {
+        return Err(format!("Invalid clock: {}", json));
+      }
+      Component::Clock { period, high, phase }
+    },
+    "mux" => Component::Mux(wires_field(json, "inputs", len)?.into(), wires_field(json, "select", len)?.into()),
+    "decoder" => Component::Decoder(wires_field(json, "select", len)?.into(), json.field("value")?.as_usize().ok_or("value must be a number")?),
+    "encoder" => Component::Encoder(wires_field(json, "inputs", len)?.into(), json.field("bit")?.as_usize().ok_or("bit must be a number")?),
+    "lut" => {
+      let table = json.field("table")?.as_str().ok_or("table must be a string")?.chars().map(|c|match c {
+        '0' => Ok(false),
+        '1' => Ok(true),
+        c => Err(format!("Invalid bit in table: {}", c)),
+      }).collect::<Result<_, String>>()?;
+      Component::Lut(wires_field(json, "inputs", len)?.into(), table)
+    },
+    "ram" => {
+      let number = |key: &str|json.field(key)?.as_usize().ok_or_else(||format!("{} must be a number", key));
+      Component::Ram(number("memory")?, wires_field(json, "address", len)?.into(), number("bit")?)
+    },
+    "source" => Component::Source(json.field("value")?.as_bool().ok_or("value must be a boolean")?),
+    "bus" => Component::Bus(bus_mode(json)?, json.field("drivers")?.as_array().ok_or("drivers must be an array")?.iter()
+      .map(|pair|match pair.as_array().map(|p|p.iter().map(Json::as_usize).collect::<Vec<_>>()).as_deref() {
+        Some(&[Some(high), Some(low)]) if high < len && low < len => Ok((high, low)),
+        _ => Err(format!("Invalid bus driver: {}", pair)),
+      })
+      .collect::<Result<_, String>>()?),
+    kind => {
+      let inputs = wires_field(json, "inputs", len)?;
+      match (kind, &inputs[..]) {
+        ("buffer", &[a]) => Component::Buffer(a),
+        ("not", &[a]) => Component::Inverter(a),
+        ("or", inputs) if !inputs.is_empty() => Component::Or(inputs.into()),
+        ("and", inputs) if !inputs.is_empty() => Component::And(inputs.into()),
+        ("nor", inputs) if !inputs.is_empty() => Component::Nor(inputs.into()),
+        ("nand", inputs) if !inputs.is_empty() => Component::Nand(inputs.into()),
+        ("xor", &[a, b]) => Component::Xor(a, b),
+        ("xnor", &[a, b]) => Component::Xnor(a, b),
+        (kind, inputs) => return Err(format!("Invalid component: {} with {} inputs", kind, inputs.len())),
+      }
+    },
+  };
+  Ok((component, init))
+}
+
+impl Circuit {
+  pub fn to_json(&self) -> Json {
+    Json::object(vec![
+      ("name", self.name().into()),
+      ("components", Json::Array(self.components().iter().map(|(c, init)|component_to_json(c, *init)).collect())),
+      ("memories", Json::Array(self.memories().iter().map(memory_to_json).collect())),
+      ("inputs", self.inputs().to_vec().into()),
+      ("outputs", self.outputs().to_vec().into()),
+      ("instances", Json::Array(self.instances().iter().map(|i|Json::object(vec![
+        ("func", i.func.as_str().into()),
+        ("label", i.label.as_str().into()),
+        ("parent", i.parent.into()),
+        ("names", Json::Array(i.names.iter().map(|(name, wire)|Json::Array(vec![name.as_str().into(), (*wire).into()])).collect())),
+      ])).collect())),
+      ("owners", (0..self.components().len()).map(|c|self.owner(c)).collect::<Vec<_>>().into()),
+    ])
+  }
+  pub fn from_json(json: &Json) -> Result<Circuit, String> {
+    let name = json.field("name")?.as_str().ok_or("name must be a string")?;
+    let components = json.field("components")?.as_array().ok_or("components must be an array")?;
+    let len = components.len();
+    let components = components.iter().map(|c|component_from_json(c, len)).collect::<Result<Vec<_>, _>>()?;
+    let memories = match json.get("memories") {
+      Some(memories) => memories.as_array().ok_or("memories must be an array")?.iter().map(|m|memory_from_json(m, len)).collect::<Result<Vec<_>, _>>()?,
+      None => vec![],
+    };
+    check_memories(&components, &memories)?;
+    let inputs = wires_field(json, "inputs", len)?;
+    if let Some(input) = inputs.iter().find(|i|!matches!(components[**i].0, Component::Source(_))) {
+      return Err(format!("The input {} is not a source", input));
+    }
+    let outputs = wires_field(json, "outputs", len)?;
+    let instances_json = json.field("instances")?.as_array().ok_or("instances must be an array")?;
+    let mut instances = Vec::with_capacity(instances_json.len());
+    for (i, instance) in instances_json.iter().enumerate() {
+      let parent = match instance.field("parent")? {
+        Json::Null if i == 0 => None,
+        parent => Some(parent.as_usize().filter(|p|*p < i).ok_or_else(||format!("Invalid parent of instance {}", i))?),
+      };
+      let names = instance.field("names")?.as_array().ok_or("names must be an array")?.iter()
+        .map(|pair|match pair.as_array() {
+          Some([name, wire]) => match (name.as_str(), wire.as_usize().filter(|w|*w < len)) {
+            (Some(name), Some(wire)) => Ok((name.to_owned(), wire)),
+            _ => Err(format!("Invalid name: {}", pair)),
+          },
+          _ => Err(format!("Invalid name: {}", pair)),
+        })
+        .collect::<Result<_, String>>()?;
+      instances.push(Instance {
+        func: instance.field("func")?.as_str().ok_or("func must be a string")?.to_owned(),
+        label: instance.field("label")?.as_str().ok_or("label must be a string")?.to_owned(),
+        parent,
+        names,
+      });
+    }
+    if instances.is_empty() {
+      instances.push(Instance { func: name.to_owned(), label: name.to_owned(), parent: None, names: vec![] });
+    }
+    let owners = wires_field(json, "owners", instances.len())?;
+    if owners.len() != len {
+      return Err(format!("Expected {} owners, but recieved {}", len, owners.len()));
+    }
+    Ok(Circuit::from_parts(WholeNew { components: components.into_boxed_slice(), memories: memories.into_boxed_slice() }, owners, instances, inputs, outputs))
+  }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use crate::base::{ BusMode, Component, Memory };
+  use crate::circuit::Circuit;
+  use crate::json::Json;
+
+  /// A circuit with one component of every kind, in a named instance.
+  pub(crate) fn every_component() -> Circuit {
+    let mut b = Circuit::builder();
+    b.set_name("top");
+    let (x, y) = (b.new_slot(), b.new_slot());
+    b.add_input(x, false);
+    b.add_input(y, true);
+    b.enter("inner");
+    b.name_wire(x, "x".to_owned());
+    let gates = [
+      b.add_component(Component::Buffer(x), false),
+      b.add_component(Component::Inverter(x), true),
+      b.add_component(Component::Or([x, y].into()), false),
+      b.add_component(Component::And([x, y].into()), false),
+      b.add_component(Component::Nor([x, y].into()), true),
+      b.add_component(Component::Nand([x, y].into()), true),
+      b.add_component(Component::Xor(x, y), false),
+      b.add_component(Component::Xnor(x, y), true),
+      b.add_component(Component::Bus(BusMode::PullUp, vec![(x, y)]), true),
+      b.add_component(Component::Bus(BusMode::Float, vec![]), false),
+      b.add_component(Component::Clock { period: 4, high: 1, phase: 2 }, false),
+      b.add_component(Component::Mux([x, y].into(), [x].into()), false),
+      b.add_component(Component::Decoder([x, y].into(), 2), false),
+      b.add_component(Component::Encoder([x, y].into(), 0), false),
+      b.add_component(Component::Lut([x, y].into(), [false, true, true, false].into()), false),
+    ];
+    b.exit();
+    let memory = b.add_memory(Memory { address: [x].into(), data: [y].into(), write: x, clock: Some(y), init: [0x1, 0xff].into() });
+    let ram = b.add_component(Component::Ram(memory, [x].into(), 0), false);
+    for wire in gates.iter().copied().chain(Some(ram)) {
+      b.add_output(wire);
+    }
+    b.build()
+  }
+
+  const SMALL: &str = r#"{
+    "name": "small",
+    "components": [
+      { "type": "source", "value": false, "init": false },
+      { "type": "not", "inputs": [0], "init": true }
+    ],
+    "inputs": [0],
+    "outputs": [1],
+    "instances": [],
+    "owners": [0, 0]
+  }"#;
+
+  fn read(src: &str) -> Result<Circuit, String> {
+    Circuit::from_json(&Json::parse(src)?)
+  }
+
+  #[test]
+  fn round_trip() {
+    let circuit = every_component();
+    let json = circuit.to_json();
+    let read = read(&json.to_string()).unwrap();
+    assert_eq!(read.to_json(), json);
+    assert_eq!(read.instance_path(read.owner(3)), "inner#0");
+    assert_eq!(read.find_wire("inner#0.x"), Some(0));
+  }
+
+  #[test]
+  fn optional_fields() {
+    let circuit = read(SMALL).unwrap();
+    assert!(circuit.memories().is_empty());
+    assert_eq!(circuit.name(), "small");
+    let bus = SMALL.replace(r#""type": "not", "inputs": [0]"#, r#""type": "bus", "drivers": [[0, 0]]"#);
+    assert!(matches!(read(&bus).unwrap().components()[1].0, Component::Bus(BusMode::Float, _)));
+  }
+
+  #[test]
+  fn errors() {
+    let replace = |from: &str, to: &str| {
+      assert!(SMALL.contains(from), "{}", from);
+      read(&SMALL.replace(from, to)).unwrap_err()
+    };
+    assert_eq!(replace(r#""inputs": [0], "init""#, r#""inputs": [2], "init""#), "Invalid wire in inputs: 2");
+    assert_eq!(replace(r#""type": "not""#, r#""type": "xor""#), "Invalid component: xor with 1 inputs");
+    assert_eq!(replace(r#""inputs": [0],"#, r#""inputs": [1],"#), "The input 1 is not a source");
+    assert_eq!(replace(r#""owners": [0, 0]"#, r#""owners": [0]"#), "Expected 2 owners, but recieved 1");
+    assert_eq!(replace(r#""init": true"#, r#""init": 1"#), "init must be a boolean");
+    assert_eq!(replace(r#""name": "small","#, ""), "Missing field: name");
+    let component = |json: &str|replace(r#"{ "type": "not", "inputs": [0], "init": true }"#, json);
+    assert_eq!(component(r#"{ "type": "bus", "mode": "weak", "drivers": [], "init": false }"#), "Unknown bus mode: \"weak\"");
+    assert_eq!(component(r#"{ "type": "bus", "drivers": [[0]], "init": false }"#), "Invalid bus driver: [0]");
+    assert_eq!(component(r#"{ "type": "lut", "inputs": [0], "table": "0x", "init": false }"#), "Invalid bit in table: x");
+    assert!(component(r#"{ "type": "clock", "period": 2, "high": 3, "phase": 0, "init": false }"#).starts_with("Invalid clock: "));
+    assert_eq!(component(r#"{ "type": "ram", "memory": 0, "address": [], "bit": 0, "init": false }"#), "A RAM bit does not match its memory");
+    let instances = |json: &str|replace(r#""instances": []"#, &format!(r#""instances": [{}]"#, json));
+    assert_eq!(instances(r#"{ "func": "small", "label": "small", "parent": 0, "names": [] }"#), "Invalid parent of instance 0");
+    assert_eq!(instances(r#"{ "func": "small", "label": "small", "parent": null, "names": [["a", 5]] }"#), "Invalid name: [\"a\",5]");
+    let memories = |json: &str|replace(r#""inputs": [0],"#, &format!(r#""inputs": [0], "memories": [{}],"#, json));
+    assert_eq!(memories(r#"{ "address": [0], "data": [1], "write": 0, "clock": 9, "words": [] }"#), "clock is not a wire");
+    assert_eq!(memories(r#"{ "address": [0], "data": [1], "write": 0, "clock": null, "words": ["12"] }"#), "Invalid word: \"12\"");
+  }
+}
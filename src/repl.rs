@@ -0,0 +1,104 @@
+use rustyline::completion::{ Completer, FilenameCompleter, Pair };
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{ Context, Helper };
+
+/// Every REPL command with its arguments and a short description, in the order `help` lists them.
+pub const COMMANDS: &[(&str, &str, &str)] = &[
+  ("set", "<bits>|<name>=<value>...", "Set the inputs, one 0 or 1 per input, or groups and inputs by name"),
+  ("group", "[<name> <ports>... [<format>]]", "Show ports such as o0..o7 as one number, least significant first, or list the groups"),
+  ("format", "<group> <format>", "Show a group as bin, hex, unsigned or signed"),
+  ("ungroup", "<name>", "Remove a group"),
+  ("run", "<steps>", "Advance the simulation, printing the outputs after each step"),
+  ("clock", "[<input> <period|off>]", "Toggle an input during run, or list the clocked inputs"),
+  ("mem", "[dump|load <memory> <path>]", "List the RAMs, or write or read the contents of one as a hex file"),
+  ("save", "<path>", "Save the simulation state"),
+  ("load", "<path> [names]", "Load a saved state, matching wires by name if asked"),
+  ("back", "[<steps>]", "Step back through the state history"),
+  ("checkpoint", "<name>", "Remember the current state under a name"),
+  ("restore", "<name>", "Return to a checkpoint"),
+  ("history", "[<size>]", "Show the state history, or limit its memory use"),
+  ("break", "[when|becomes <condition>]", "Stop run while a condition holds or when it becomes true, or list breakpoints"),
+  ("watch", "<wire>", "Stop run when a wire changes"),
+  ("clear", "[<index>]", "Remove one or every breakpoint"),
+  ("explore", "[<limit> [<path>]]", "Explore the reachable states, printing a table or writing a dot graph"),
+  ("dot", "<path> [cluster]", "Write the circuit as a dot graph"),
+  ("tree", "", "Print the call hierarchy"),
+  ("blocks", "<path>", "Write the call hierarchy as a dot block diagram"),
+  ("verilog", "<path>", "Write the design as structural Verilog"),
+  ("blif", "<path> [delay]", "Write the circuit as BLIF"),
+  ("echo", "<text>", "Print text"),
+  ("source", "<path>", "Run the commands in a script"),
+  ("help", "[<command>]", "Describe the commands"),
+  ("exit", "", "Quit"),
+];
+
+/// Commands whose first argument is a path.
+const PATH_COMMANDS: &[&str] = &["save", "load", "dot", "blocks", "verilog", "blif", "source"];
+
+pub fn help(command: Option<&str>) -> Result<(), String> {
+  let commands: Vec<_> = match command {
+    None => COMMANDS.iter().collect(),
+    Some(name) => vec![COMMANDS.iter().find(|(cmd, _, _)|*cmd == name).ok_or_else(||format!("Unknown command: {}", name))?],
+  };
+  let width = commands.iter().map(|(cmd, args, _)|cmd.len() + args.len() + 1).max().unwrap_or(0);
+  for (cmd, args, description) in commands {
+    println!("{:width$}  {}", format!("{} {}", cmd, args), description, width = width);
+  }
+  Ok(())
+}
+
+/// Completes command names, paths and the names of the circuit's wires.
+pub struct ReplHelper {
+  names: Vec<String>,
+  files: FilenameCompleter,
+}
+
+impl ReplHelper {
+  pub fn new(names: Vec<String>) -> Self {
+    ReplHelper { names, files: FilenameCompleter::new() }
+  }
+}
+
+impl Completer for ReplHelper {
+  type Candidate = Pair;
+  fn complete(&self, line: &str, pos: usize, ctx: &Context) -> rustyline::Result<(usize, Vec<Pair>)> {
+    let line = &line[..pos];
+    let start = line.rfind(char::is_whitespace).map_or(0, |i|i + 1);
+    let word = &line[start..];
+    let mut previous = line[..start].split_whitespace();
+    let candidates: Vec<&str> = match previous.next() {
+      None => {
+        let pairs = COMMANDS.iter()
+          .filter(|(cmd, _, _)|cmd.starts_with(word))
+          .map(|(cmd, _, _)|Pair { display: cmd.to_string(), replacement: format!("{} ", cmd) })
+          .collect();
+        return Ok((start, pairs));
+      },
+      Some("help") => COMMANDS.iter().map(|(cmd, _, _)|*cmd).collect(),
+      Some("mem") => match previous.count() {
+        0 => vec!["dump", "load"],
+        2 => return self.files.complete(line, pos, ctx),
+        _ => vec![],
+      },
+      Some(cmd) if PATH_COMMANDS.contains(&cmd) && previous.next().is_none() => return self.files.complete(line, pos, ctx),
+      Some(_) => self.names.iter().map(String::as_str).collect(),
+    };
+    let pairs = candidates.into_iter()
+      .filter(|c|c.starts_with(word))
+      .map(|c|Pair { display: c.to_owned(), replacement: c.to_owned() })
+      .collect();
+    Ok((start, pairs))
+  }
+}
+
+impl Hinter for ReplHelper {
+  type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
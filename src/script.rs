@@ -0,0 +1,39 @@
+/// A parsed REPL script.
+/// Each line holds one command, `#` at the start of a word starts a comment, and `repeat N {` ... `}` runs the enclosed commands N times.
+pub enum Command {
+  Line(usize, Vec<String>),
+  Repeat(usize, Vec<Command>),
+}
+
+/// Splits a line into words, up to a comment.
+/// A `#` only starts a comment at the start of a word, as instance wire names like `half_adder#0.c` contain one.
+pub fn words(line: &str) -> Vec<&str> {
+  line.split_whitespace().take_while(|word|!word.starts_with('#')).collect()
+}
+
+pub fn parse(src: &str) -> Result<Vec<Command>, String> {
+  // The commands of every open block, with the line and count of the `repeat` that opened it.
+  let mut blocks: Vec<(usize, usize, Vec<Command>)> = vec![(0, 1, vec![])];
+  for (line_no, line) in src.lines().enumerate().map(|(i, l)|(i + 1, l)) {
+    match words(line)[..] {
+      [] => {},
+      ["repeat", count, "{"] => {
+        let count = count.parse().map_err(|_|format!("line {}: Not a number: {}", line_no, count))?;
+        blocks.push((line_no, count, vec![]));
+      },
+      ["repeat", ..] => return Err(format!("line {}: Expected repeat <count> {{", line_no)),
+      ["}"] => {
+        if blocks.len() == 1 {
+          return Err(format!("line {}: Unmatched }}", line_no));
+        }
+        let (_, count, body) = blocks.pop().unwrap();
+        blocks.last_mut().unwrap().2.push(Command::Repeat(count, body));
+      },
+      ref words => blocks.last_mut().unwrap().2.push(Command::Line(line_no, words.iter().map(|w|w.to_string()).collect())),
+    }
+  }
+  if blocks.len() > 1 {
+    return Err(format!("line {}: Unclosed repeat block", blocks.last().unwrap().0));
+  }
+  Ok(blocks.pop().unwrap().2)
+}
@@ -0,0 +1,282 @@
+//! The state file format.
+//!
+//! A state file is a text file starting with the line `circuit-sim state 2`, followed by
+//! - `circuit <name> <fingerprint>` with the fingerprint in hexadecimal,
+//! - `step <count>`,
+//! - `wires <bits>` with the value of every wire, in wire order,
+//! - one `memory <index> <words>...` line per RAM, with its words in hex,
+//! - one `input <name> <bit>` line per input source,
+//! - one `wire <name> <bit>` line per named wire, using the qualified names of `Circuit::wire_names`.
+//!
+//! The wire values are restored exactly when the fingerprint matches. Otherwise the inputs and
+//! named wires can be restored by name, leaving the rest of the circuit untouched. RAM contents
+//! are restored by index whenever the sizes match.
+//! Version 1 files, which are the same without the `memory` lines, are still accepted.
+use std::fs::{ read_to_string, File };
+use std::io::{ self, BufWriter, Write };
+use std::path::Path;
+use crate::base::{ Data, WholeNewState };
+use crate::circuit::Circuit;
+use crate::memory::parse_hex;
+
+const HEADER: &str = "circuit-sim state";
+const VERSION: usize = 2;
+
+/// How many saved values found a wire when restoring by name.
+pub struct Restored {
+  pub matched: usize,
+  pub missing: Vec<String>,
+}
+
+fn bit(c: &str) -> Result<Data, String> {
+  match c {
+    "0" => Ok(false),
+    "1" => Ok(true),
+    c => Err(format!("'{}' is not a valid bit value", c)),
+  }
+}
+
+fn bit_char(b: Data) -> char {
+  if b { '1' } else { '0' }
+}
+
+struct StateFile {
+  name: String,
+  fingerprint: u64,
+  steps: usize,
+  wires: Vec<Data>,
+  inputs: Vec<(String, Data)>,
+  names: Vec<(String, Data)>,
+  memories: Vec<(usize, Vec<u64>)>,
+}
+
+fn parse(src: &str) -> Result<StateFile, String> {
+  let mut lines = src.lines().enumerate().map(|(i, l)|(i + 1, l.trim())).filter(|(_, l)|!l.is_empty());
+  let version = match lines.next() {
+    Some((_, line)) if line.starts_with(HEADER) => line[HEADER.len()..].trim(),
+    _ => return Err("Not a state file".to_owned()),
+  };
+  if !(1..=VERSION).any(|v|version == v.to_string()) {
+    return Err(format!("Unsupported state file version {}, expected {}", version, VERSION));
+  }
+  let mut file = StateFile { name: String::new(), fingerprint: 0, steps: 0, wires: vec![], inputs: vec![], names: vec![], memories: vec![] };
+  let mut found_circuit = false;
+  for (line_no, line) in lines {
+    let err = |msg: String|format!("line {}: {}", line_no, msg);
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words[..] {
+      ["circuit", name, fingerprint] => {
+        file.name = name.to_owned();
+        file.fingerprint = u64::from_str_radix(fingerprint, 16).map_err(|_|err(format!("Invalid fingerprint: {}", fingerprint)))?;
+        found_circuit = true;
+      },
+      ["step", steps] => file.steps = steps.parse().map_err(|_|err(format!("Not a number: {}", steps)))?,
+      ["wires", bits] => file.wires = bits.chars().map(|c|bit(&c.to_string())).collect::<Result<_, _>>().map_err(err)?,
+      ["memory", index, ref words @ ..] => {
+        let index = index.parse().map_err(|_|err(format!("Not a number: {}", index)))?;
+        file.memories.push((index, parse_hex(&words.join(" ")).map_err(err)?));
+      },
+      ["input", name, value] => file.inputs.push((name.to_owned(), bit(value).map_err(err)?)),
+      ["wire", name, value] => file.names.push((name.to_owned(), bit(value).map_err(err)?)),
+      _ => return Err(err(format!("Unexpected line: {}", line))),
+    }
+  }
+  if !found_circuit {
+    return Err("The state file does not name its circuit".to_owned());
+  }
+  Ok(file)
+}
+
+/// Copies saved RAM contents into the memories of the same index and size, returning the rest.
+fn restore_memories(state: &mut WholeNewState, memories: Vec<(usize, Vec<u64>)>) -> Vec<String> {
+  let mut missing = vec![];
+  for (i, words) in memories {
+    match state.memories.get_mut(i).filter(|m|m.len() == words.len()) {
+      Some(memory) => memory.copy_from_slice(&words),
+      None => missing.push(format!("memory {}", i)),
+    }
+  }
+  missing
+}
+
+impl Circuit {
+  pub fn save_state<P: AsRef<Path>>(&self, state: &WholeNewState, path: P) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "{} {}", HEADER, VERSION)?;
+    writeln!(out, "circuit {} {:016x}", self.name(), self.fingerprint())?;
+    writeln!(out, "step {}", state.steps)?;
+    writeln!(out, "wires {}", state.components.iter().copied().map(bit_char).collect::<String>())?;
+    for (i, words) in state.memories.iter().enumerate() {
+      write!(out, "memory {}", i)?;
+      words.iter().try_for_each(|w|write!(out, " {:x}", w))?;
+      writeln!(out)?;
+    }
+    for (input, value) in self.inputs().iter().zip(self.get_input()) {
+      writeln!(out, "input {} {}", self.port_name(*input).unwrap_or("_"), bit_char(value))?;
+    }
+    for (name, wire) in self.wire_names() {
+      writeln!(out, "wire {} {}", name, bit_char(state.components[wire]))?;
+    }
+    out.flush()
+  }
+  /// Restores a saved state, by wire names if `by_name` is set and the circuit has changed since it was saved.
+  /// Files without a header are read as the bare bit strings written by `WholeNewState::save`.
+  pub fn load_state<P: AsRef<Path>>(&mut self, state: &mut WholeNewState, path: P, by_name: bool) -> Result<Option<Restored>, String> {
+    let src = read_to_string(&path).map_err(|e|format!("{}", e))?;
+    if !src.starts_with(HEADER) {
+      state.load(path).map_err(|e|format!("{}", e))?;
+      return Ok(None);
+    }
+    let file = parse(&src)?;
+    let mut inputs = self.get_input();
+    if file.fingerprint == self.fingerprint() {
+      let memories_match = file.memories.iter().all(|(i, words)|state.memories.get(*i).is_some_and(|m|m.len() == words.len()));
+      if file.wires.len() != state.components.len() || file.inputs.len() != inputs.len() || !memories_match {
+        return Err("The state file does not match its fingerprint".to_owned());
+      }
+      for (wire, value) in file.wires.into_iter().enumerate() {
+        state.set(wire, value);
+      }
+      restore_memories(state, file.memories);
+      self.set_input(file.inputs.into_iter().map(|(_, v)|v).collect())?;
+      state.steps = file.steps;
+      return Ok(None);
+    }
+    if !by_name {
+      return Err(format!("The state was saved from a different version of {}, load it by name to restore the matching wires", file.name));
+    }
+    let mut restored = Restored { matched: 0, missing: restore_memories(state, file.memories) };
+    for (name, value) in file.inputs {
+      match self.inputs().iter().position(|i|self.port_name(*i) == Some(&name)) {
+        Some(i) => {
+          inputs[i] = value;
+          restored.matched += 1;
+        },
+        None => restored.missing.push(name),
+      }
+    }
+    let names = self.wire_names();
+    for (name, value) in file.names {
+      match names.iter().find(|(n, _)|*n == name) {
+        Some((_, wire)) => {
+          state.set(*wire, value);
+          restored.matched += 1;
+        },
+        None => restored.missing.push(name),
+      }
+    }
+    self.set_input(inputs)?;
+    state.steps = file.steps;
+    Ok(Some(restored))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::parse;
+  use crate::base::Component;
+  use crate::circuit::Circuit;
+  use crate::netlist::tests::every_component;
+  use std::fs::{ remove_file, write };
+  use std::path::PathBuf;
+
+  /// A file in the temporary directory, removed when dropped.
+  struct TempFile(PathBuf);
+
+  impl TempFile {
+    fn new(name: &str) -> Self {
+      TempFile(std::env::temp_dir().join(format!("circuit-sim-{}-{}.state", std::process::id(), name)))
+    }
+  }
+
+  impl Drop for TempFile {
+    fn drop(&mut self) {
+      let _ = remove_file(&self.0);
+    }
+  }
+
+  /// Inputs `a` and `b`, with `n` their nand, and with `extra` another component so the fingerprint differs.
+  fn named(extra: bool) -> Circuit {
+    let mut b = Circuit::builder();
+    b.set_name("named");
+    let (a, c) = (b.new_slot(), b.new_slot());
+    b.add_input(a, false);
+    b.add_input(c, false);
+    b.name_wire(a, "a".to_owned());
+    b.name_wire(c, "b".to_owned());
+    if extra {
+      b.add_component(Component::Source(true), true);
+    }
+    let n = b.add_component(Component::Nand([a, c].into()), true);
+    b.name_wire(n, "n".to_owned());
+    b.add_output(n);
+    b.build()
+  }
+
+  #[test]
+  fn round_trip() {
+    let file = TempFile::new("round_trip");
+    let mut circuit = every_component();
+    let mut state = circuit.new_state();
+    circuit.set_input(vec![true, true]).unwrap();
+    for _ in 0..5 {
+      circuit.update(&mut state);
+    }
+    circuit.save_state(&state, &file.0).unwrap();
+    circuit.set_input(vec![false, false]).unwrap();
+    let mut loaded = circuit.new_state();
+    assert!(circuit.load_state(&mut loaded, &file.0, false).unwrap().is_none());
+    assert_eq!((loaded.components, loaded.memories, loaded.steps), (state.components, state.memories, state.steps));
+    assert_eq!(circuit.get_input(), [true, true]);
+  }
+
+  #[test]
+  fn by_name() {
+    let file = TempFile::new("by_name");
+    let mut circuit = named(false);
+    let mut state = circuit.new_state();
+    circuit.set_input(vec![true, true]).unwrap();
+    for _ in 0..3 {
+      circuit.update(&mut state);
+    }
+    circuit.save_state(&state, &file.0).unwrap();
+    let mut changed = named(true);
+    let mut loaded = changed.new_state();
+    let err = changed.load_state(&mut loaded, &file.0, false).err().unwrap();
+    assert_eq!(err, "The state was saved from a different version of named, load it by name to restore the matching wires");
+    let restored = changed.load_state(&mut loaded, &file.0, true).unwrap().unwrap();
+    assert_eq!((restored.matched, restored.missing.len()), (5, 0));
+    assert_eq!(changed.get_input(), [true, true]);
+    assert_eq!(changed.get_output(&loaded), [false]);
+    assert_eq!(loaded.steps, 3);
+  }
+
+  #[test]
+  fn older_formats() {
+    let file = TempFile::new("older_formats");
+    let mut circuit = named(false);
+    let mut state = circuit.new_state();
+    write(&file.0, format!("circuit-sim state 1\ncircuit named {:x}\nstep 2\nwires 110\ninput a 1\ninput b 1\n", circuit.fingerprint())).unwrap();
+    circuit.load_state(&mut state, &file.0, false).unwrap();
+    assert_eq!((&state.components[..], state.steps), (&[true, true, false][..], 2));
+    write(&file.0, "011\n").unwrap();
+    assert!(circuit.load_state(&mut state, &file.0, false).unwrap().is_none());
+    assert_eq!(&state.components[..], [false, true, true]);
+  }
+
+  #[test]
+  fn errors() {
+    let err = |src: &str|parse(src).err().unwrap();
+    assert_eq!(err("state\n"), "Not a state file");
+    assert_eq!(err("circuit-sim state 3\n"), "Unsupported state file version 3, expected 2");
+    assert_eq!(err("circuit-sim state 2\nstep 1\n"), "The state file does not name its circuit");
+    assert_eq!(err("circuit-sim state 2\ncircuit c 0\nwires 012\n"), "line 3: '2' is not a valid bit value");
+    assert_eq!(err("circuit-sim state 2\ncircuit c xyz\n"), "line 2: Invalid fingerprint: xyz");
+    assert_eq!(err("circuit-sim state 2\nwatch a\n"), "line 2: Unexpected line: watch a");
+    let file = TempFile::new("errors");
+    let mut circuit = named(false);
+    let mut state = circuit.new_state();
+    write(&file.0, format!("circuit-sim state 2\ncircuit named {:x}\nwires 1\n", circuit.fingerprint())).unwrap();
+    assert_eq!(circuit.load_state(&mut state, &file.0, false).err().unwrap(), "The state file does not match its fingerprint");
+  }
+}
@@ -0,0 +1,195 @@
+use pest_derive::Parser;
+use pest::Parser;
+use std::fmt::{ self, Display, Formatter };
+use crate::base::{ Data, WholeNewState };
+use crate::circuit::Circuit;
+type Pair<'i> = pest::iterators::Pair<'i, Rule>;
+
+#[derive(Parser)]
+#[grammar = "condition.pest"]
+struct ConditionParser;
+
+/// A boolean expression over named wires, such as `o3 == 1 && carry == 0`.
+#[derive(Debug)]
+pub enum Condition {
+  Const(Data),
+  Wire(String, usize),
+  Not(Box<Condition>),
+  And(Vec<Condition>),
+  Or(Vec<Condition>),
+  Eq(Box<Condition>, Box<Condition>),
+  Ne(Box<Condition>, Box<Condition>),
+}
+
+impl Condition {
+  pub fn parse(s: &str, circuit: &Circuit) -> Result<Condition, String> {
+    let pair = ConditionParser::parse(Rule::condition, s).map_err(|e|format!("{}", e))?.next().unwrap();
+    Condition::from_pair(pair, circuit)
+  }
+  fn from_pair(pair: Pair, circuit: &Circuit) -> Result<Condition, String> {
+    Ok(match pair.as_rule() {
+      Rule::bit => Condition::Const(pair.as_str() == "1"),
+      Rule::name => {
+        let name = pair.as_str();
+        let wire = circuit.find_wire(name).ok_or_else(||format!("Unknown wire: {}", name))?;
+        Condition::Wire(name.to_owned(), wire)
+      },
+      Rule::not => Condition::Not(Box::new(Condition::from_pair(pair.into_inner().next().unwrap(), circuit)?)),
+      Rule::or | Rule::and => {
+        let rule = pair.as_rule();
+        let mut items = pair.into_inner().map(|p|Condition::from_pair(p, circuit)).collect::<Result<Vec<_>, _>>()?;
+        if items.len() == 1 {
+          items.pop().unwrap()
+        } else if rule == Rule::or {
+          Condition::Or(items)
+        } else {
+          Condition::And(items)
+        }
+      },
+      Rule::compare => {
+        let mut pairs = pair.into_inner();
+        let a = Box::new(Condition::from_pair(pairs.next().unwrap(), circuit)?);
+        let op = pairs.next().unwrap().as_str();
+        let b = Box::new(Condition::from_pair(pairs.next().unwrap(), circuit)?);
+        if op == "==" { Condition::Eq(a, b) } else { Condition::Ne(a, b) }
+      },
+      r => unreachable!("{:?}", r),
+    })
+  }
+  pub fn eval(&self, wires: &[Data]) -> Data {
+    match self {
+      Condition::Const(b) => *b,
+      Condition::Wire(_, wire) => wires[*wire],
+      Condition::Not(c) => !c.eval(wires),
+      Condition::And(items) => items.iter().all(|c|c.eval(wires)),
+      Condition::Or(items) => items.iter().any(|c|c.eval(wires)),
+      Condition::Eq(a, b) => a.eval(wires) == b.eval(wires),
+      Condition::Ne(a, b) => a.eval(wires) != b.eval(wires),
+    }
+  }
+  /// The named wires the condition reads, in order of first use.
+  pub fn wires(&self) -> Vec<(&str, usize)> {
+    let mut wires = vec![];
+    self.collect_wires(&mut wires);
+    wires
+  }
+  fn collect_wires<'a>(&'a self, wires: &mut Vec<(&'a str, usize)>) {
+    match self {
+      Condition::Const(_) => {},
+      Condition::Wire(name, wire) => if !wires.iter().any(|(n, _)|n == name) {
+        wires.push((name, *wire))
+      },
+      Condition::Not(c) => c.collect_wires(wires),
+      Condition::And(items) | Condition::Or(items) => items.iter().for_each(|c|c.collect_wires(wires)),
+      Condition::Eq(a, b) | Condition::Ne(a, b) => {
+        a.collect_wires(wires);
+        b.collect_wires(wires);
+      },
+    }
+  }
+}
+
+pub enum Breakpoint {
+  /// Stops while the condition holds.
+  When(String, Condition),
+  /// Stops only when the condition changes from false to true.
+  Becomes(String, Condition),
+  Watch(String, usize),
+}
+
+impl Display for Breakpoint {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match self {
+      Breakpoint::When(src, _) => write!(f, "break when {}", src),
+      Breakpoint::Becomes(src, _) => write!(f, "break becomes {}", src),
+      Breakpoint::Watch(name, _) => write!(f, "watch {}", name),
+    }
+  }
+}
+
+/// A breakpoint that triggered during an update.
+pub struct Hit<'a> {
+  pub breakpoint: &'a Breakpoint,
+  pub step: usize,
+  pub values: Vec<(&'a str, Data, Data)>,
+}
+
+impl<'a> Display for Hit<'a> {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    write!(f, "Step {}: {}", self.step, self.breakpoint)?;
+    for (i, (name, before, after)) in self.values.iter().enumerate() {
+      f.write_str(if i == 0 { " (" } else { ", " })?;
+      if before == after {
+        write!(f, "{}={}", name, *after as u8)?;
+      } else {
+        write!(f, "{}: {} -> {}", name, *before as u8, *after as u8)?;
+      }
+    }
+    if !self.values.is_empty() {
+      f.write_str(")")?;
+    }
+    Ok(())
+  }
+}
+
+#[derive(Default)]
+pub struct Breakpoints {
+  pub list: Vec<Breakpoint>,
+}
+
+impl Breakpoints {
+  /// Adds a condition to stop at, either whenever it holds or only when it becomes true.
+  pub fn add_condition(&mut self, src: &str, circuit: &Circuit, edge: bool) -> Result<(), String> {
+    let condition = Condition::parse(src, circuit)?;
+    self.list.push(if edge { Breakpoint::Becomes(src.to_owned(), condition) } else { Breakpoint::When(src.to_owned(), condition) });
+    Ok(())
+  }
+  pub fn add_watch(&mut self, name: &str, circuit: &Circuit) -> Result<(), String> {
+    let wire = circuit.find_wire(name).ok_or_else(||format!("Unknown wire: {}", name))?;
+    self.list.push(Breakpoint::Watch(name.to_owned(), wire));
+    Ok(())
+  }
+  pub fn is_empty(&self) -> bool {
+    self.list.is_empty()
+  }
+}
+
+impl Circuit {
+  /// Updates the circuit, returning the conditions that hold or became true and the watched wires that changed.
+  pub fn update_checked<'a>(&mut self, state: &mut WholeNewState, breakpoints: &'a Breakpoints) -> Vec<Hit<'a>> {
+    if breakpoints.is_empty() {
+      self.update(state);
+      return vec![];
+    }
+    let before = state.components.clone();
+    self.update(state);
+    let after = &state.components;
+    breakpoints.list.iter().filter_map(|breakpoint|{
+      let values = match breakpoint {
+        Breakpoint::When(_, condition) => {
+          if !condition.eval(after) {
+            return None;
+          }
+          condition.wires()
+        },
+        Breakpoint::Becomes(_, condition) => {
+          if condition.eval(&before) || !condition.eval(after) {
+            return None;
+          }
+          condition.wires()
+        },
+        Breakpoint::Watch(name, wire) => {
+          if before[*wire] == after[*wire] {
+            return None;
+          }
+          vec![(name.as_str(), *wire)]
+        },
+      };
+      Some(Hit {
+        breakpoint,
+        step: state.steps,
+        values: values.into_iter().map(|(name, wire)|(name, before[wire], after[wire])).collect(),
+      })
+    }).collect()
+  }
+}
@@ -0,0 +1,262 @@
+use std::collections::{ HashMap, HashSet };
+use crate::base::Component;
+use crate::circuit::{ Builder, Circuit };
+use crate::json::Json;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Bit {
+  Net(usize),
+  Const(bool),
+}
+
+fn parse_bit(bit: &Json) -> Result<Bit, String> {
+  match bit {
+    Json::Number(_) => bit.as_usize().map(Bit::Net).ok_or_else(||format!("Invalid net: {}", bit)),
+    Json::String(s) => match s.as_str() {
+      "1" => Ok(Bit::Const(true)),
+      "0" | "x" | "z" => Ok(Bit::Const(false)),
+      s => Err(format!("Invalid constant: {}", s)),
+    },
+    bit => Err(format!("Invalid bit: {}", bit)),
+  }
+}
+
+fn port_names(name: &str, width: usize) -> Vec<String> {
+  if width == 1 {
+    vec![name.to_owned()]
+  } else {
+    (0..width).map(|i|format!("{}[{}]", name, i)).collect()
+  }
+}
+
+struct YosysReader {
+  builder: Builder,
+  nets: HashMap<usize, usize>,
+  driven: HashSet<usize>,
+}
+
+impl YosysReader {
+  fn wire(&mut self, bit: Bit) -> usize {
+    match bit {
+      Bit::Net(net) => {
+        let builder = &mut self.builder;
+        *self.nets.entry(net).or_insert_with(||builder.new_slot())
+      },
+      Bit::Const(b) => self.builder.add_component(Component::Source(b), b),
+    }
+  }
+  fn output(&mut self, bit: Bit) -> Result<usize, String> {
+    match bit {
+      Bit::Net(_) => {
+        let slot = self.wire(bit);
+        if !self.driven.insert(slot) {
+          return Err("A net is driven more than once".to_owned());
+        }
+        Ok(slot)
+      },
+      Bit::Const(_) => Ok(self.builder.new_slot()),
+    }
+  }
+  fn cell(&mut self, name: &str, cell: &Json) -> Result<(), String> {
+    let kind = cell.get("type").and_then(Json::as_str).ok_or_else(||format!("Cell {} has no type", name))?;
+    let connections = cell.get("connections").ok_or_else(||format!("Cell {} has no connections", name))?;
+    let pin = |pin: &str| -> Result<Bit, String> {
+      let bits = connections.get(pin).and_then(Json::as_array).ok_or_else(||format!("Cell {} has no {} connection", name, pin))?;
+      match bits {
+        [bit] => parse_bit(bit),
+        _ => Err(format!("Cell {} connects {} bits to {}", name, bits.len(), pin)),
+      }
+    };
+    let y = if kind.starts_with("$_DFF") { pin("Q")? } else { pin("Y")? };
+    let inputs: Vec<Bit> = match kind {
+      "$_BUF_" | "$_NOT_" => vec![pin("A")?],
+      "$_AND_" | "$_OR_" | "$_NAND_" | "$_NOR_" | "$_XOR_" | "$_XNOR_" => vec![pin("A")?, pin("B")?],
+      "$_MUX_" => vec![pin("A")?, pin("B")?, pin("S")?],
+      "$_DFF_N_" | "$_DFF_P_" => vec![pin("C")?, pin("D")?],
+      kind if kind.starts_with("$_DFF_") && kind.len() == 10 => vec![pin("C")?, pin("D")?, pin("R")?],
+      kind => return Err(format!("Unsupported cell type {} in cell {}; only flattened gate level netlists can be imported", kind, name)),
+    };
+    let inputs: Vec<usize> = inputs.into_iter().map(|bit|self.wire(bit)).collect();
+    let y = self.output(y)?;
+    let b = &mut self.builder;
+    match (kind, &inputs[..]) {
+      ("$_BUF_", &[a]) => b.place_component(y, Component::Buffer(a), false),
+      ("$_NOT_", &[a]) => b.place_component(y, Component::Inverter(a), true),
+      ("$_AND_", &[a, c]) => b.place_component(y, Component::And([a, c].into()), false),
+      ("$_OR_", &[a, c]) => b.place_component(y, Component::Or([a, c].into()), false),
+      ("$_NAND_", &[a, c]) => b.place_component(y, Component::Nand([a, c].into()), true),
+      ("$_NOR_", &[a, c]) => b.place_component(y, Component::Nor([a, c].into()), true),
+      ("$_XOR_", &[a, c]) => b.place_component(y, Component::Xor(a, c), false),
+      ("$_XNOR_", &[a, c]) => b.place_component(y, Component::Xnor(a, c), true),
+      ("$_MUX_", &[a, c, s]) => b.place_component(y, Component::Mux([a, c].into(), [s].into()), false),
+      (kind, inputs) => {
+        let mut clock = inputs[0];
+        if kind.as_bytes()[6] == b'N' {
+          clock = b.add_component(Component::Inverter(clock), true);
+        }
+        let enable = b.add_rising_edge(clock);
+        let (mut set, mut reset) = b.add_d_latch_input(inputs[1], enable);
+        if let Some(&r) = inputs.get(2) {
+          let r = if kind.as_bytes()[7] == b'N' { b.add_component(Component::Inverter(r), true) } else { r };
+          let nr = b.add_component(Component::Inverter(r), true);
+          let (to, from) = if kind.as_bytes()[8] == b'1' { (&mut set, &mut reset) } else { (&mut reset, &mut set) };
+          *to = b.add_component(Component::Or([*to, r].into()), false);
+          *from = b.add_component(Component::And([*from, nr].into()), false);
+        }
+        b.place_sr_latch(y, set, reset, false);
+      },
+    }
+    Ok(())
+  }
+}
+
+impl Circuit {
+  /// Reads a flattened gate level module written by `yosys write_json`.
+  /// Without a module name the module marked as top, or the only module, is used.
+  pub fn read_yosys_json(src: &str, module: Option<&str>) -> Result<Circuit, String> {
+    Circuit::from_yosys_json(&Json::parse(src)?, module)
+  }
+  pub fn from_yosys_json(json: &Json, module: Option<&str>) -> Result<Circuit, String> {
+    let modules = json.get("modules").and_then(Json::as_object).ok_or("Not a Yosys netlist")?;
+    let (name, module) = match module {
+      Some(name) => modules.iter().find(|(n, _)|n == name).ok_or_else(||format!("Unknown module: {}", name))?,
+      None => modules.iter()
+        .find(|(_, m)|m.get("attributes").and_then(|a|a.get("top")).is_some())
+        .or(if modules.len() == 1 { modules.first() } else { None })
+        .ok_or("The netlist has several modules, but none is marked as top")?,
+    };
+    let mut reader = YosysReader { builder: Circuit::builder(), nets: HashMap::new(), driven: HashSet::new() };
+    reader.builder.set_name(name);
+    let ports = module.get("ports").and_then(Json::as_object).ok_or("The module has no ports")?;
+    let mut outputs = vec![];
+    for (port, def) in ports {
+      let bits = def.get("bits").and_then(Json::as_array).ok_or_else(||format!("Port {} has no bits", port))?;
+      let bits = bits.iter().map(parse_bit).collect::<Result<Vec<_>, _>>()?;
+      let names = port_names(port, bits.len());
+      match def.get("direction").and_then(Json::as_str) {
+        Some("input") => for (bit, name) in bits.into_iter().zip(names) {
+          let slot = reader.output(bit)?;
+          reader.builder.add_input(slot, false);
+          reader.builder.name_wire(slot, name);
+        },
+        Some("output") => outputs.extend(bits.into_iter().zip(names)),
+        _ => return Err(format!("Port {} must be an input or an output", port)),
+      }
+    }
+    for (name, cell) in module.get("cells").and_then(Json::as_object).unwrap_or(&[]) {
+      reader.cell(name, cell)?;
+    }
+    for (bit, name) in outputs {
+      let slot = reader.wire(bit);
+      reader.builder.add_output(slot);
+      reader.builder.name_wire(slot, name);
+    }
+    if let Some(netnames) = module.get("netnames").and_then(Json::as_object) {
+      for (name, def) in netnames.iter().filter(|(n, _)|!n.starts_with('$') && !ports.iter().any(|(p, _)|p == n)) {
+        let bits = def.get("bits").and_then(Json::as_array).map_or(&[][..], |b|b);
+        for (bit, name) in bits.iter().zip(port_names(name, bits.len())) {
+          if let Ok(Bit::Net(net)) = parse_bit(bit) {
+            if let Some(slot) = reader.nets.get(&net).copied() {
+              reader.builder.name_wire(slot, name);
+            }
+          }
+        }
+      }
+    }
+    if let Some((net, _)) = reader.nets.iter().find(|(_, slot)|!reader.driven.contains(slot)) {
+      return Err(format!("The net {} is never driven", net));
+    }
+    Ok(reader.builder.build())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::circuit::Circuit;
+
+  /// A module with a 2 bit input `a`, the xor of its bits on `x`, and `x` delayed by a flip-flop clocked by `c` on `q`.
+  const NETLIST: &str = r#"{
+    "modules": {
+      "top": {
+        "attributes": { "top": "00000000000000000000000000000001" },
+        "ports": {
+          "a": { "direction": "input", "bits": [ 2, 3 ] },
+          "c": { "direction": "input", "bits": [ 4 ] },
+          "x": { "direction": "output", "bits": [ 5 ] },
+          "q": { "direction": "output", "bits": [ 6 ] }
+        },
+        "cells": {
+          "$xor": { "type": "$_XOR_", "connections": { "A": [ 2 ], "B": [ 3 ], "Y": [ 5 ] } },
+          "$dff": { "type": "$_DFF_P_", "connections": { "C": [ 4 ], "D": [ 5 ], "Q": [ 6 ] } }
+        },
+        "netnames": {
+          "inner": { "bits": [ 5 ] }
+        }
+      },
+      "other": { "ports": {} }
+    }
+  }"#;
+
+  /// A netlist of one module called `m` with an input `a`, an output `y` and `cells`.
+  fn module(cells: &str) -> String {
+    format!(r#"{{ "modules": {{ "m": {{
+      "ports": {{ "a": {{ "direction": "input", "bits": [ 2 ] }}, "y": {{ "direction": "output", "bits": [ 3 ] }} }},
+      "cells": {{ {} }}
+    }} }} }}"#, cells)
+  }
+
+  fn read_err(src: &str) -> String {
+    Circuit::read_yosys_json(src, None).unwrap_err()
+  }
+
+  #[test]
+  fn import() {
+    let mut circuit = Circuit::read_yosys_json(NETLIST, None).unwrap();
+    assert_eq!(circuit.name(), "top");
+    assert_eq!(circuit.find_port("a[1]"), Some((true, 1)));
+    assert_eq!(circuit.find_port("q"), Some((false, 1)));
+    assert!(circuit.find_wire("inner").is_some());
+    let mut state = circuit.new_state();
+    let mut step = |circuit: &mut Circuit, inputs: [bool; 3]| {
+      circuit.set_input(inputs.to_vec()).unwrap();
+      for _ in 0..32 {
+        circuit.update(&mut state);
+      }
+      circuit.get_output(&state)
+    };
+    assert_eq!(step(&mut circuit, [true, false, false]), [true, false]);
+    assert_eq!(step(&mut circuit, [true, false, true]), [true, true]);
+    assert_eq!(step(&mut circuit, [true, true, true]), [false, true]);
+    assert_eq!(step(&mut circuit, [true, true, false]), [false, true]);
+    assert_eq!(step(&mut circuit, [true, true, true]), [false, false]);
+  }
+
+  #[test]
+  fn module_choice() {
+    assert_eq!(Circuit::read_yosys_json(NETLIST, Some("other")).unwrap().name(), "other");
+    assert_eq!(Circuit::read_yosys_json(NETLIST, Some("none")).unwrap_err(), "Unknown module: none");
+    let untagged = r#"{ "modules": { "a": { "ports": {} }, "b": { "ports": {} } } }"#;
+    assert_eq!(read_err(untagged), "The netlist has several modules, but none is marked as top");
+    assert_eq!(Circuit::read_yosys_json(r#"{ "modules": { "a": { "ports": {} } } }"#, None).unwrap().name(), "a");
+  }
+
+  #[test]
+  fn errors() {
+    assert_eq!(read_err("{}"), "Not a Yosys netlist");
+    assert_eq!(read_err(r#"{ "modules": { "m": {} } }"#), "The module has no ports");
+    assert_eq!(read_err(r#"{ "modules": { "m": { "ports": { "a": { "direction": "input" } } } } }"#), "Port a has no bits");
+    assert_eq!(read_err(r#"{ "modules": { "m": { "ports": { "a": { "direction": "inout", "bits": [ 2 ] } } } } }"#), "Port a must be an input or an output");
+    assert_eq!(read_err(r#"{ "modules": { "m": { "ports": { "a": { "direction": "input", "bits": [ "2" ] } } } } }"#), "Invalid constant: 2");
+    assert_eq!(read_err(r#"{ "modules": { "m": { "ports": { "a": { "direction": "input", "bits": [ true ] } } } } }"#), "Invalid bit: true");
+    assert_eq!(read_err(&module(r#""g": { "connections": {} }"#)), "Cell g has no type");
+    assert_eq!(read_err(&module(r#""g": { "type": "$_NOT_" }"#)), "Cell g has no connections");
+    assert_eq!(read_err(&module(r#""g": { "type": "$_NOT_", "connections": { "Y": [ 3 ] } }"#)), "Cell g has no A connection");
+    assert_eq!(read_err(&module(r#""g": { "type": "$_NOT_", "connections": { "A": [ 2, 2 ], "Y": [ 3 ] } }"#)), "Cell g connects 2 bits to A");
+    assert_eq!(
+      read_err(&module(r#""g": { "type": "$add", "connections": { "Y": [ 3 ] } }"#)),
+      "Unsupported cell type $add in cell g; only flattened gate level netlists can be imported",
+    );
+    assert_eq!(read_err(&module(r#""g": { "type": "$_NOT_", "connections": { "A": [ 2 ], "Y": [ 2 ] } }"#)), "A net is driven more than once");
+    assert_eq!(read_err(&module("")), "The net 3 is never driven");
+  }
+}